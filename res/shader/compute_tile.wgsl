@@ -3,6 +3,47 @@ struct VertexOutput {
     [[location(0)]] uv: vec2<REAL>;
 };
 
+// The raw sample is spread over multiple targets
+struct FragmentOutput {
+    [[location(0)]] target0: vec4<f32>;
+    [[location(1)]] target1: vec4<f32>;
+};
+
+struct Sample {
+    // smooth escape time
+    iter: REAL;
+
+    // exterior or interior distance estimate in world units
+    de: REAL;
+
+    // direction of the surface normal
+    normal: REAL;
+
+    // closest distance to the orbit trap
+    trap_dist: REAL;
+    trap_iter: REAL;
+
+    // |z| after the last iteration
+    z_abs: REAL;
+
+    // period of the attracting cycle, zero if not found
+    period: REAL;
+
+    // atom domain, iteration with the smallest |z|
+    atom: REAL;
+};
+
+// z and its derivatives after one period of the cycle
+struct Cycle {
+    z: vec2<REAL>;
+
+    // dz/dz0 and dz/dc
+    d1: vec4<REAL>;
+
+    // d/dz0 of d1
+    d2: vec4<REAL>;
+};
+
 fn cpx_sqr(z: vec2<REAL>) -> vec2<REAL> {
     return vec2<REAL>(
         z.x*z.x - z.y*z.y,
@@ -24,29 +65,162 @@ fn cpx_abs(z: vec2<REAL>) -> vec2<REAL> {
     );
 }
 
-fn mandel(c: vec2<REAL>) -> REAL {
+fn cpx_mul(a: vec2<REAL>, b: vec2<REAL>) -> vec2<REAL> {
+    return vec2<REAL>(
+        a.x*b.x - a.y*b.y,
+        a.x*b.y + a.y*b.x
+    );
+}
+
+fn cpx_div(a: vec2<REAL>, b: vec2<REAL>) -> vec2<REAL> {
+    let d = dot(b, b);
+    return vec2<REAL>(
+        (a.x*b.x + a.y*b.y) / d,
+        (a.y*b.x - a.x*b.y) / d
+    );
+}
+
+fn cycle(c: vec2<REAL>, z0: vec2<REAL>, period: u32) -> Cycle {
+    let flip_x = vec4<REAL>(-1.0, 1.0, -1.0, 1.0);
+    let flip_y = vec4<REAL>(1.0, -1.0, 1.0, -1.0);
+
+    var z: vec2<REAL> = z0;
+    var d1: vec4<REAL> = vec4<REAL>(1.0, 0.0, 0.0, 0.0);
+    var d2: vec4<REAL> = vec4<REAL>(0.0, 0.0, 0.0, 0.0);
+
+    var i: u32 = 0u;
+    loop {
+        if (i >= period) {
+            break;
+        }
+
+        @IMPL_INTERIOR@
+
+        i = i + 1u;
+    }
+
+    var out: Cycle;
+    out.z = z;
+    out.d1 = d1;
+    out.d2 = d2;
+    return out;
+}
+
+// interior distance estimate for a point on an attracting cycle
+fn interior_distance(c: vec2<REAL>, z_start: vec2<REAL>, period: u32) -> REAL {
+    let one = vec2<REAL>(1.0, 0.0);
+
+    // the detected point is only close to the cycle, refine it with newton
+    var z0: vec2<REAL> = z_start;
+    var i: u32 = 0u;
+    loop {
+        if (i >= 4u) {
+            break;
+        }
+
+        let r = cycle(c, z0, period);
+        z0 = z0 - cpx_div(r.z - z0, r.d1.xy - one);
+        i = i + 1u;
+    }
+
+    let r = cycle(c, z0, period);
+    let d_z = r.d1.xy;
+    let d_c = r.d1.zw;
+    let d_zz = r.d2.xy;
+    let d_zc = r.d2.zw;
+    let de = (REAL(1.0) - dot(d_z, d_z)) / length(d_zc + cpx_div(cpx_mul(d_zz, d_c), one - d_z));
+    return max(de, REAL(0.0));
+}
+
+fn trap_distance(z: vec2<REAL>) -> REAL {
+    let p0 = z - vec2<REAL>(@TRAP_CENTER@);
+
+    // rotate into the frame of the trap
+    let rot = vec2<REAL>(@TRAP_ROTATION@);
+    let p = vec2<REAL>(p0.x*rot.x + p0.y*rot.y, p0.y*rot.x - p0.x*rot.y);
+
+    return @TRAP@;
+}
+
+fn mandel(c: vec2<REAL>) -> Sample {
     var z: vec2<REAL> = vec2<REAL>(0.0, 0.0);
 
+    // derivative dz/dc
+    var dz: vec2<REAL> = vec2<REAL>(0.0, 0.0);
+
     var i: u32 = 0u;
     var t: REAL = REAL(0.0);
 
+    var s: Sample;
+    s.de = REAL(0.0);
+    s.normal = REAL(0.0);
+    s.trap_dist = REAL(1.0e30);
+    s.trap_iter = REAL(0.0);
+    s.period = REAL(0.0);
+    s.atom = REAL(0.0);
+
+    var atom_dist: REAL = REAL(1.0e30);
+
+    // if the orbit comes back to this point it is periodic
+    var z_ref: vec2<REAL> = vec2<REAL>(0.0, 0.0);
+    var z_ref_iter: u32 = 0u;
+
     loop {
-        if (i >= 1024u) {
+        if (i >= @ITER_COUNT@u) {
+            // never escaped
+            t = REAL(-1.0);
             break;
         }
 
         @IMPL@
 
+        let n = i + 1u;
         let d = z.x*z.x + z.y*z.y;
+
+        let trap_dist = trap_distance(z);
+        if (trap_dist < s.trap_dist) {
+            s.trap_dist = trap_dist;
+            s.trap_iter = t;
+        }
+
+        if (d < atom_dist) {
+            atom_dist = d;
+            s.atom = REAL(n);
+        }
+
         if (d > REAL(256.0)) {
             t = t - log2(log2(d)) + REAL(4.0);
+
+            let z_abs = sqrt(d);
+            s.de = z_abs * log(z_abs) / length(dz);
+
+            let u = cpx_mul(z, vec2<REAL>(dz.x, -dz.y));
+            s.normal = atan2(u.y, u.x);
+            break;
+        }
+
+        let dr = z - z_ref;
+        if (dot(dr, dr) < REAL(1.0e-10)) {
+            // the orbit is periodic, it will never escape
+            let period = n - z_ref_iter;
+            s.period = REAL(period);
+            s.de = interior_distance(c, z, period);
+            t = REAL(-1.0);
             break;
         }
 
+        // move the reference point further along, every power of two
+        if ((n & (n - 1u)) == 0u) {
+            z_ref = z;
+            z_ref_iter = n;
+        }
+
         i = i + 1u;
     }
 
-    return t;
+    s.iter = t;
+    s.z_abs = length(z);
+    return s;
 }
 
 [[stage(vertex)]]
@@ -61,27 +235,14 @@ fn vs_main(
     return out;
 }
 
+// The output is the raw sample, coloring happens later on the cpu
 [[stage(fragment)]]
-fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    let t0 = f32(mandel(in.uv));
-
-    let pi_3 = 1.04719755119659774615421446109316763;
-    var a: f32 = (1.0 - ((t0*t0) / (1024.0*1024.0)));
-    a = max(min(a, 1.0), 0.0);
-    let t = t0 * 0.005;
-    var r: f32 = a * sin((0.5 - t) * pi_3 * 3.0 + pi_3 * 0.0);
-    var g: f32 = a * sin((0.5 - t) * pi_3 * 3.0 + pi_3 * 1.0);
-    var b: f32 = a * sin((0.5 - t) * pi_3 * 3.0 + pi_3 * 2.0);
-
-    r = r * r;
-    g = g * g;
-    b = b * b;
-
-    // FIXME: Gamma correction, are we in srgb or linear space now?
-    r = r * r;
-    g = g * g;
-    b = b * b;
-
-    // FIXME: images are RGBA, but our target is BGRA, so we swap A and R here to make it work kind of
-    return vec4<f32>(b, g, r, 1.0);
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let pixel_size = abs(dpdx(in.uv.x));
+    let s = mandel(in.uv);
+
+    var out: FragmentOutput;
+    out.target0 = vec4<f32>(f32(s.iter), f32(s.de / pixel_size), f32(s.normal), f32(s.trap_dist));
+    out.target1 = vec4<f32>(f32(s.trap_iter), f32(s.z_abs), f32(s.period), f32(s.atom));
+    return out;
 }
@@ -2,6 +2,7 @@ struct VertexOutput {
     [[builtin(position)]] pos: vec4<f32>;
     [[location(0)]]       uv: vec2<f32>;
     [[location(1)]]       ix: i32;
+    [[location(2)]]       color: vec4<f32>;
 };
 
 [[block]]
@@ -22,18 +23,21 @@ var<uniform> uniform: UniformData;
 fn vs_main(
     [[location(0)]] pos: vec2<f32>,
     [[location(1)]] uv: vec2<f32>,
-    [[location(2)]] ix: i32
+    [[location(2)]] ix: i32,
+    [[location(3)]] color: vec4<f32>
 ) -> VertexOutput {
     var out: VertexOutput;
     out.pos = vec4<f32>(pos.xy * 1.0 / uniform.resolution * 2.0 - 1.0, 0.0, 1.0);
     out.pos.y = out.pos.y * -1.0;
     out.uv = uv;
     out.ix = ix;
+    out.color = color;
     return out;
 }
 
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // The alpha of the tile itself is ignored, tiles are only transparent while fading in
     let col = textureSample(texture, sampler, in.uv, in.ix);
-    return col;
+    return vec4<f32>(col.rgb * in.color.rgb, in.color.a);
 }
@@ -262,7 +262,7 @@ impl AssetLoader {
 
         let img = loop {
             let data = STATIC_RES_FILES.get_file(path).unwrap().contents();
-            let buf = ::image::load_from_memory(&data);
+            let buf = ::image::load_from_memory(data);
             let buf = match buf {
                 Ok(buf) => buf,
                 Err(_) => {
@@ -279,7 +279,7 @@ impl AssetLoader {
 
         self.image_cache
             .insert(path.to_string(), (img.clone(), SystemTime::now()));
-        return img;
+        img
     }
 }
 
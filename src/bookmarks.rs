@@ -0,0 +1,404 @@
+use std::path::Path;
+
+use ::image::codecs::png::PngEncoder;
+use ::image::ColorType;
+use ::image::ImageEncoder;
+use serde::Deserialize;
+use serde::Serialize;
+use winit::event::VirtualKeyCode;
+
+use crate::asset_loader::FontType;
+use crate::asset_loader::TextAlignment;
+use crate::builder::TileBuilder;
+use crate::color::Coloring;
+use crate::export;
+use crate::fractal::FractalStep;
+use crate::history::HistoryEntry;
+use crate::image::Image;
+use crate::state::State;
+use crate::update_loop::Input;
+use crate::util::*;
+use crate::viewport::Location;
+use crate::viewport::Viewport;
+
+const BOOKMARKS_DIR: &str = ".fractal-toy";
+const BOOKMARKS_FILE: &str = ".fractal-toy/bookmarks.ron";
+
+/// Exported to and imported from the working directory, so it is easy to find and share
+const EXPORT_FILE: &str = "fractal-toy-bookmarks.ron";
+
+/// Size of the thumbnails in pixels, they show the center of the screen
+const THUMBNAIL_SIZE: V2<u32> = V2::new(96, 64);
+
+/// A bookmark without a thumbnail gets one when the view is this close to it, in zoom levels and
+/// radians. The view has to be less than a pixel away from it too.
+const THUMBNAIL_TOLERANCE: f64 = 0.01;
+
+/// Number of bookmarks visible at the same time, scroll to see the others
+const VISIBLE_ROWS: usize = 6;
+
+const WINDOW_WIDTH: f64 = 440.0;
+const ROW_HEIGHT: f64 = 76.0;
+const PADDING: f64 = 8.0;
+const BUTTON_SIZE: V2 = V2::new(90.0, 30.0);
+const TEXT_SIZE: f32 = 22.0;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub location: Location,
+    pub steps: Vec<FractalStep>,
+
+    /// PNG encoded, so the bookmarks file stays small
+    #[serde(default)]
+    thumbnail: Vec<u8>,
+
+    /// The decoded thumbnail
+    #[serde(skip)]
+    image: Option<Image>,
+}
+
+impl Bookmark {
+    fn image(&mut self) -> Option<Image> {
+        if self.image.is_none() && !self.thumbnail.is_empty() {
+            self.image = decode_png(&self.thumbnail);
+        }
+        self.image.clone()
+    }
+
+    fn is_same_place(&self, other: &Bookmark) -> bool {
+        self.location == other.location && self.steps == other.steps
+    }
+}
+
+/// Saved locations, shown in a window with a thumbnail for each of them
+///
+/// Changes are written to the bookmarks file right away. Bookmarks can be exported to a file in
+/// the working directory, and imported from there or by dropping a file on the window.
+pub struct Bookmarks {
+    pub visible: bool,
+
+    list: Vec<Bookmark>,
+
+    /// Index of the first visible bookmark
+    scroll: usize,
+
+    /// The bookmark whose name is being typed
+    editing: Option<usize>,
+}
+
+impl Bookmarks {
+    /// Load the bookmarks file, there are no bookmarks if it does not exist
+    pub fn load() -> Self {
+        let list = match read_file(Path::new(BOOKMARKS_FILE)) {
+            Ok(list) => list,
+            Err(e) => {
+                if Path::new(BOOKMARKS_FILE).exists() {
+                    eprintln!("Could not read {}: {}", BOOKMARKS_FILE, e);
+                }
+                Vec::new()
+            }
+        };
+
+        Bookmarks {
+            visible: false,
+            list,
+            scroll: 0,
+            editing: None,
+        }
+    }
+
+    fn save(&self) {
+        let result = std::fs::create_dir_all(BOOKMARKS_DIR)
+            .and_then(|_| write_file(Path::new(BOOKMARKS_FILE), &self.list));
+        if let Err(e) = result {
+            eprintln!("Could not save the bookmarks: {}", e);
+        }
+    }
+
+    /// A name is being typed, so the keyboard should not control anything else
+    pub fn is_typing(&self) -> bool {
+        self.visible && self.editing.is_some()
+    }
+
+    /// Add the bookmarks of a file, skipping the places we already have
+    pub fn import(&mut self, path: &Path) -> Result<usize, String> {
+        let list = read_file(path)?;
+        let mut count = 0;
+        for b in list {
+            if !self.list.iter().any(|x| x.is_same_place(&b)) {
+                self.list.push(b);
+                count += 1;
+            }
+        }
+        self.save();
+        Ok(count)
+    }
+
+    pub fn export(&self) -> std::io::Result<()> {
+        write_file(Path::new(EXPORT_FILE), &self.list)
+    }
+
+    /// Bookmark the current view, and start typing its name
+    fn add(
+        &mut self,
+        viewport: &Viewport,
+        builder: &mut TileBuilder,
+        coloring: &Coloring,
+        steps: &[FractalStep],
+    ) {
+        let image = export::render(viewport, builder, coloring, THUMBNAIL_SIZE);
+        self.list.push(Bookmark {
+            name: format!("Bookmark {}", self.list.len() + 1),
+            location: viewport.location(),
+            steps: steps.to_vec(),
+            thumbnail: encode_png(&image),
+            image: Some(image),
+        });
+
+        let last = self.list.len() - 1;
+        self.editing = Some(last);
+        self.scroll = (last + 1).saturating_sub(VISIBLE_ROWS);
+        self.save();
+    }
+
+    /// Bookmark a location we are not looking at, the thumbnail is rendered once we get there
+    pub fn add_location(&mut self, name: String, location: Location, steps: &[FractalStep]) {
+        self.list.push(Bookmark {
+            name,
+            location,
+            steps: steps.to_vec(),
+            thumbnail: Vec::new(),
+            image: None,
+        });
+
+        self.visible = true;
+        self.scroll = self.list.len().saturating_sub(VISIBLE_ROWS);
+        self.save();
+    }
+
+    /// Render the missing thumbnail of the bookmark on the screen, when all its tiles are built
+    fn fill_thumbnail(
+        &mut self,
+        viewport: &Viewport,
+        builder: &mut TileBuilder,
+        coloring: &Coloring,
+        steps: &[FractalStep],
+    ) {
+        if viewport.is_flying() {
+            return;
+        }
+
+        let here = viewport.location();
+        let pixel_size = viewport.pixel_size();
+        let missing = self.list.iter_mut().find(|b| {
+            b.thumbnail.is_empty()
+                && b.steps == steps
+                && (b.location.offset - here.offset).magnitude() < pixel_size
+                && (b.location.zoom - here.zoom).abs() < THUMBNAIL_TOLERANCE
+                && (b.location.rotation - here.rotation).abs() < THUMBNAIL_TOLERANCE
+        });
+
+        if let Some(b) = missing {
+            let tiles = viewport.get_pos_all(0);
+            if tiles.iter().all(|p| builder.has_tile(p)) {
+                let image = export::render(viewport, builder, coloring, THUMBNAIL_SIZE);
+                b.thumbnail = encode_png(&image);
+                b.image = Some(image);
+                self.save();
+            }
+        }
+    }
+
+    /// Show the window, returns the bookmark that was clicked
+    pub fn update(
+        &mut self,
+        state: &mut State,
+        input: &Input,
+        viewport: &Viewport,
+        builder: &mut TileBuilder,
+        coloring: &Coloring,
+        steps: &[FractalStep],
+    ) -> Option<HistoryEntry> {
+        for path in input.dropped_files.iter() {
+            match self.import(path) {
+                Ok(n) => eprintln!("Imported {} bookmarks from {}", n, path.display()),
+                Err(e) => eprintln!("Could not import {}: {}", path.display(), e),
+            }
+        }
+
+        self.fill_thumbnail(viewport, builder, coloring, steps);
+
+        if !self.visible {
+            return None;
+        }
+
+        self.type_name(input);
+
+        let mut window = state.ui.begin_window("bookmarks", V2::new(20.0, 20.0));
+        let mut clicked = None;
+
+        // Header with the buttons for all bookmarks
+        let mut pos = V2::new(PADDING, PADDING);
+        window.text(
+            &mut state.asset,
+            FontType::Normal,
+            (pos + V2::new(0.0, BUTTON_SIZE.y * 0.5)).map(|x| x as _),
+            V2::new(TextAlignment::Left, TextAlignment::Center),
+            TEXT_SIZE,
+            "Bookmarks",
+        );
+        pos.x = WINDOW_WIDTH - PADDING - BUTTON_SIZE.x * 3.0;
+        let mut header = [false; 3];
+        for (clicked, label) in header.iter_mut().zip(["add", "import", "export"]) {
+            let rect = Rect::corner_size(pos, BUTTON_SIZE);
+            *clicked = window.button(&mut state.ui, &mut state.asset, rect, label, TEXT_SIZE);
+            pos.x += BUTTON_SIZE.x;
+        }
+        let [add, import, export] = header;
+
+        // One row per bookmark, with the thumbnail on the left
+        let max_scroll = self.list.len().saturating_sub(VISIBLE_ROWS);
+        self.scroll = self.scroll.min(max_scroll);
+        let mut delete = None;
+        let mut y = PADDING * 2.0 + BUTTON_SIZE.y;
+        for i in (self.scroll..self.list.len()).take(VISIBLE_ROWS) {
+            let thumb_size = THUMBNAIL_SIZE.map(|x| x as f64);
+            let thumb = Rect::corner_size(V2::new(PADDING, y), thumb_size);
+            match self.list[i].image() {
+                Some(img) => window.image(thumb, img),
+                None => window.image(thumb, state.asset.image("missing.png")),
+            }
+            if state.ui.region(&window.to_screen(&thumb)).click {
+                let b = &self.list[i];
+                clicked = Some(HistoryEntry {
+                    location: b.location,
+                    steps: b.steps.clone(),
+                });
+            }
+
+            let mut name = self.list[i].name.clone();
+            if self.editing == Some(i) {
+                name.push('_');
+            }
+            let x = PADDING * 2.0 + thumb_size.x;
+            window.text(
+                &mut state.asset,
+                FontType::Normal,
+                V2::new(x, y + thumb_size.y * 0.25).map(|x| x as _),
+                V2::new(TextAlignment::Left, TextAlignment::Center),
+                TEXT_SIZE,
+                &name,
+            );
+
+            let mut pos = V2::new(x, y + thumb_size.y - BUTTON_SIZE.y);
+            let label = if self.editing == Some(i) {
+                "done"
+            } else {
+                "rename"
+            };
+            let rect = Rect::corner_size(pos, BUTTON_SIZE);
+            if window.button(&mut state.ui, &mut state.asset, rect, label, TEXT_SIZE) {
+                self.editing = match self.editing {
+                    Some(j) if j == i => None,
+                    _ => Some(i),
+                };
+                self.save();
+            }
+            pos.x += BUTTON_SIZE.x + PADDING;
+            let rect = Rect::corner_size(pos, BUTTON_SIZE);
+            if window.button(&mut state.ui, &mut state.asset, rect, "delete", TEXT_SIZE) {
+                delete = Some(i);
+            }
+
+            y += ROW_HEIGHT;
+        }
+
+        // Keep the size of the window the same while scrolling
+        let rows = self.list.len().clamp(1, VISIBLE_ROWS) as f64;
+        let bottom = PADDING * 2.0 + BUTTON_SIZE.y + rows * ROW_HEIGHT;
+        window.reserve(Rect::min_max(V2::zero(), V2::new(WINDOW_WIDTH, bottom)));
+
+        let hover = window.content_rect().contains(input.mouse.map(|x| x as _));
+        if hover && input.mouse_scroll < 0.0 {
+            self.scroll += 1;
+        }
+        if hover && input.mouse_scroll > 0.0 {
+            self.scroll = self.scroll.saturating_sub(1);
+        }
+        state.ui.end_window(window);
+
+        if let Some(i) = delete {
+            self.list.remove(i);
+            self.editing = None;
+            self.save();
+        }
+
+        if add {
+            self.add(viewport, builder, coloring, steps);
+        }
+
+        if import {
+            match self.import(Path::new(EXPORT_FILE)) {
+                Ok(n) => eprintln!("Imported {} bookmarks from {}", n, EXPORT_FILE),
+                Err(e) => eprintln!("Could not import {}: {}", EXPORT_FILE, e),
+            }
+        }
+
+        if export {
+            match self.export() {
+                Ok(()) => eprintln!("Exported {} bookmarks to {}", self.list.len(), EXPORT_FILE),
+                Err(e) => eprintln!("Could not export the bookmarks: {}", e),
+            }
+        }
+
+        clicked
+    }
+
+    /// Edit the name of the bookmark being renamed
+    fn type_name(&mut self, input: &Input) {
+        let i = match self.editing {
+            Some(i) if i < self.list.len() => i,
+            _ => return,
+        };
+
+        let name = &mut self.list[i].name;
+        name.push_str(&input.text);
+        if input.key_click(VirtualKeyCode::Back) {
+            name.pop();
+        }
+
+        if input.key_click(VirtualKeyCode::Return) || input.key_click(VirtualKeyCode::Escape) {
+            self.editing = None;
+            self.save();
+        }
+    }
+}
+
+fn encode_png(img: &Image) -> Vec<u8> {
+    let mut png = Vec::new();
+    let size = img.size();
+    let result =
+        PngEncoder::new(&mut png).write_image(img.data(), size.x, size.y, ColorType::Rgba8);
+    if let Err(e) = result {
+        eprintln!("Could not encode the thumbnail: {}", e);
+        png.clear();
+    }
+    png
+}
+
+fn decode_png(png: &[u8]) -> Option<Image> {
+    let buf = ::image::load_from_memory(png).ok()?.into_rgba8();
+    let (w, h) = buf.dimensions();
+    Some(Image::new(V2::new(w, h), buf.into_raw()))
+}
+
+fn read_file(path: &Path) -> Result<Vec<Bookmark>, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    ron::from_str(&data).map_err(|e| e.to_string())
+}
+
+fn write_file(path: &Path, list: &[Bookmark]) -> std::io::Result<()> {
+    let data = ron::ser::to_string_pretty(list, ron::ser::PrettyConfig::default()).unwrap();
+    std::fs::write(path, data)
+}
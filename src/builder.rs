@@ -1,91 +1,362 @@
-use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
 use crossbeam_channel::bounded;
 use crossbeam_channel::Receiver;
-use crossbeam_channel::Sender;
+use instant::Instant;
 
 use crate::asset_loader::AssetLoader;
 use crate::debug::Debug;
+use crate::disk_cache::DiskCache;
+use crate::domain::Domain;
 use crate::fractal::FractalStep;
+use crate::fractal::OrbitTrap;
 use crate::gpu::compute_tile::ComputeTile;
 use crate::gpu::GpuDevice;
 use crate::image::Image;
+use crate::sampling::Sampling;
+use crate::settings::WorkerConfig;
+use crate::tile_cache::CacheStats;
+use crate::tile_cache::TileCache;
+use crate::tile_data::Sample;
+use crate::tile_data::TileData;
+use crate::tile_queue::TileQueue;
 use crate::tilemap::TilePos;
 use crate::util::*;
 
-const ITER_COUNT: usize = 1024;
+/// Maximum number of iterations, points that did not escape by then are inside the set
+pub const DEFAULT_ITER_COUNT: u32 = 1024;
+
+/// Pixel spacing of the progressive passes for cpu tiles, every pass is sent as soon as it is done
+const PASSES: [u32; 4] = [8, 4, 2, 1];
+
+/// A finished tile in the cache
+pub struct Tile {
+    pub data: TileData,
+
+    /// The coloured tile, and the colouring version it was made with
+    pub image: Option<(Image, u32)>,
+
+    /// When the first pass of this tile arrived, used to fade it in
+    pub received: Instant,
+}
+
+impl Tile {
+    /// Approximate memory used by this tile
+    pub fn bytes(&self) -> usize {
+        let sample = std::mem::size_of::<Sample>();
+        let image = match &self.image {
+            Some((img, _)) => img.data().len(),
+            None => 0,
+        };
+
+        let supersampled = match &self.data.supersampled {
+            Some(s) => s.pixels.len() * 4 + s.samples.len() * sample,
+            None => 0,
+        };
+
+        self.data.samples.len() * sample + supersampled + self.data.histogram.len() * 4 + image
+    }
+}
 
 pub struct TileBuilder {
-    cache: BTreeMap<TilePos, Option<(Image, u32)>>,
+    cache: TileCache,
+
+    /// Tiles requested since the last update, queued tiles that are not requested are cancelled
+    requested: BTreeSet<TilePos>,
+
+    /// Deeper tiles need more precision than the gpu has, they are built on the cpu
+    gpu_max_z: u8,
+
+    gpu_queue: Arc<TileQueue>,
+    queue: Arc<TileQueue>,
+
+    receiver: Receiver<(TilePos, TileData)>,
+
+    workers: Vec<Worker>,
+
+    /// Tells the workers to stop immediately, discarding the tile they are working on
+    abort: Arc<AtomicBool>,
+
+    /// Workers that stopped unexpectedly
+    errors: Vec<String>,
+}
+
+struct Worker {
+    name: String,
+
+    /// None once the thread is joined
+    handle: Option<JoinHandle<()>>,
+
+    /// Where the worker gets its tiles
+    queue: Arc<TileQueue>,
+
+    stats: Arc<WorkerStats>,
+    started: Instant,
+}
+
+/// Counters updated by the worker thread
+#[derive(Default)]
+struct WorkerStats {
+    tiles: AtomicU64,
+
+    /// Time spent building tiles, the rest of the time the worker was waiting for work
+    busy_micros: AtomicU64,
 
-    gpu_sender: Sender<TilePos>,
+    /// The tile the worker is building, given back to the queue if the worker panics
+    building: Mutex<Option<TilePos>>,
+}
+
+impl WorkerStats {
+    /// Wait for the next tile, see `TileQueue::pop`
+    fn pop(&self, queue: &TileQueue) -> Option<TilePos> {
+        let p = queue.pop();
+        *self.building.lock().unwrap() = p;
+        p
+    }
+
+    fn finish(&self, queue: &TileQueue, p: &TilePos) {
+        *self.building.lock().unwrap() = None;
+        queue.finish(p);
+    }
+
+    /// A tile was finished, that was started at `start`
+    fn record(&self, start: Instant) {
+        let micros = start.elapsed().as_micros() as u64;
+        self.tiles.fetch_add(1, Ordering::Relaxed);
+        self.busy_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+}
+
+/// Throughput of a single worker, since it was started
+pub struct WorkerMetrics {
+    pub name: String,
+    pub tiles: u64,
+    pub tiles_per_second: f64,
 
-    sender: Sender<(TilePos, V2)>,
-    receiver: Receiver<(TilePos, Image)>,
+    /// Fraction of the time spent building tiles
+    pub busy: f64,
+}
+
+impl Worker {
+    fn spawn(
+        name: String,
+        background: bool,
+        queue: &Arc<TileQueue>,
+        f: impl FnOnce(&WorkerStats, &TileQueue) + Send + 'static,
+    ) -> Self {
+        let stats = Arc::new(WorkerStats::default());
+        let thread_stats = Arc::clone(&stats);
+        let thread_queue = Arc::clone(queue);
+        let handle = std::thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || {
+                if background {
+                    lower_thread_priority();
+                }
+                f(&thread_stats, &thread_queue)
+            })
+            .unwrap();
+
+        Worker {
+            name,
+            handle: Some(handle),
+            queue: Arc::clone(queue),
+            stats,
+            started: Instant::now(),
+        }
+    }
+
+    fn metrics(&self) -> WorkerMetrics {
+        let tiles = self.stats.tiles.load(Ordering::Relaxed);
+        let busy = self.stats.busy_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let elapsed = self.started.elapsed().as_secs_f64().max(1e-3);
+        WorkerMetrics {
+            name: self.name.clone(),
+            tiles,
+            tiles_per_second: tiles as f64 / elapsed,
+            busy: (busy / elapsed).min(1.0),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.handle.as_ref().is_none_or(|h| h.is_finished())
+    }
+
+    /// Wait for the thread to exit, returns an error message if it panicked.
+    ///
+    /// The tile it was building is given back to the queue, so it can be built again.
+    fn join(&mut self) -> Result<(), String> {
+        let handle = match self.handle.take() {
+            Some(handle) => handle,
+            None => return Ok(()),
+        };
+
+        handle.join().map_err(|e| {
+            // The lock is never held while building, so it is not poisoned
+            if let Some(p) = self.stats.building.lock().unwrap().take() {
+                self.queue.finish(&p);
+            }
+
+            let msg = match (e.downcast_ref::<&str>(), e.downcast_ref::<String>()) {
+                (Some(msg), _) => msg.to_string(),
+                (_, Some(msg)) => msg.clone(),
+                _ => "unknown error".to_string(),
+            };
+            format!("{} panicked: {}", self.name, msg)
+        })
+    }
 }
 
 impl TileBuilder {
+    /// Bits in the mantissa of the backends, the gpu computes with f32 and the cpu with f64
+    const GPU_PRECISION: u32 = f32::MANTISSA_DIGITS;
+    const CPU_PRECISION: u32 = f64::MANTISSA_DIGITS;
+
+    /// The domain of a formula with the precision of these backends
+    pub fn domain(alg: &[FractalStep]) -> Domain {
+        Domain::new(alg, Self::GPU_PRECISION, Self::CPU_PRECISION)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         gpu: Arc<GpuDevice>,
         asset_loader: &mut AssetLoader,
         alg: &[FractalStep],
+        trap: &OrbitTrap,
+        sampling: Sampling,
+        iter_count: u32,
+        cache_budget: usize,
+        disk_cache_limit: Option<u64>,
+        config: &WorkerConfig,
     ) -> TileBuilder {
-        let (req_send, req_recv) = bounded::<(TilePos, V2)>(16);
-        let (tile_send, tile_recv) = bounded::<(TilePos, Image)>(16);
+        let (tile_send, tile_recv) = bounded::<(TilePos, TileData)>(16);
+
+        let abort = Arc::new(AtomicBool::new(false));
+        let mut workers = Vec::new();
 
-        let (req_send_gpu, req_recv_gpu) = bounded::<TilePos>(16);
-        {
-            let gpu_builder = ComputeTile::load(alg, &gpu, asset_loader);
+        // Every job has its own pipeline and targets, so they can't get in each others way
+        let gpu_queue = Arc::new(TileQueue::new());
+        for i in 0..config.gpu_jobs() {
+            let gpu_builder = ComputeTile::load(alg, trap, iter_count, &gpu, asset_loader);
             let gpu_device = Arc::clone(&gpu);
             let tile_send = tile_send.clone();
-            std::thread::spawn(move || {
-                while let Ok(pos) = req_recv_gpu.recv() {
+            let abort = Arc::clone(&abort);
+            let name = format!("gpu worker {}", i);
+            let work = move |stats: &WorkerStats, queue: &TileQueue| {
+                while let Some(pos) = stats.pop(queue) {
+                    let start = Instant::now();
                     Debug::push("builder.gpu.build()");
-                    let img = gpu_builder.build(&gpu_device, &pos);
+                    let mut data = gpu_builder.build(&gpu_device, &pos);
+
+                    // The extra samples are rendered as whole tiles too
+                    if sampling != Sampling::Single {
+                        let supersampled = sampling.supersample(&data, |pixels, o| {
+                            if abort.load(Ordering::Relaxed) {
+                                return None;
+                            }
+                            let samples = gpu_builder.render(&gpu_device, &pos, o);
+                            Some(pixels.iter().map(|i| samples[*i as usize]).collect())
+                        });
+                        data.supersampled = supersampled;
+                    }
                     Debug::pop();
-                    if let Err(_) = tile_send.send((pos, img)) {
+                    stats.record(start);
+                    let open = tile_send.send((pos, data)).is_ok();
+                    stats.finish(queue, &pos);
+                    if !open {
                         break;
                     }
                 }
-            });
+            };
+            workers.push(Worker::spawn(name, config.background, &gpu_queue, work));
         }
 
-        for _ in 0..(num_cpus::get() as i32 - 1).max(1) {
+        // Everything the samples depend on, the extra samples are not stored
+        let disk_cache = disk_cache_limit.map(|limit| {
+            let definition = ron::to_string(&(alg, trap, iter_count)).unwrap();
+            DiskCache::new(&definition, limit)
+        });
+
+        let queue = Arc::new(TileQueue::new());
+        for i in 0..config.cpu_workers() {
             let tile_send = tile_send.clone();
-            let req_recv = req_recv.clone();
+            let abort = Arc::clone(&abort);
 
+            let disk_cache = disk_cache.clone();
             let alg = alg.to_vec();
-            std::thread::spawn(move || {
-                while let Ok((pos, a)) = req_recv.recv() {
+            let trap = *trap;
+            let name = format!("cpu worker {}", i);
+            let work = move |stats: &WorkerStats, queue: &TileQueue| {
+                while let Some(pos) = stats.pop(queue) {
+                    let start = Instant::now();
+                    if let Some(mut data) = disk_cache.as_ref().and_then(|d| d.load(&pos)) {
+                        Self::supersample(
+                            &alg, &trap, iter_count, sampling, &pos, &abort, &mut data,
+                        );
+                        stats.record(start);
+                        let open = tile_send.send((pos, data)).is_ok();
+                        stats.finish(queue, &pos);
+                        if !open {
+                            break;
+                        }
+                        continue;
+                    }
+
                     Debug::push("builder.cpu.build()");
-                    let img = Self::gen_tile(&alg, &pos, a);
+                    let mut open = true;
+                    Self::gen_tile(&alg, &trap, iter_count, &pos, &abort, |mut data| {
+                        if data.is_complete() {
+                            if let Some(disk_cache) = &disk_cache {
+                                disk_cache.store(&pos, &data);
+                            }
+                            Self::supersample(
+                                &alg, &trap, iter_count, sampling, &pos, &abort, &mut data,
+                            );
+                            stats.record(start);
+                        }
+                        open = tile_send.send((pos, data)).is_ok();
+                        open
+                    });
                     Debug::pop();
-                    if let Err(_) = tile_send.send((pos, img)) {
+                    stats.finish(queue, &pos);
+                    if !open {
                         break;
                     }
                 }
-            });
+            };
+            workers.push(Worker::spawn(name, config.background, &queue, work));
         }
 
         TileBuilder {
-            cache: BTreeMap::new(),
-            gpu_sender: req_send_gpu,
-            sender: req_send,
+            cache: TileCache::new(cache_budget),
+            requested: BTreeSet::new(),
+            gpu_max_z: Self::domain(alg).gpu_max_z,
+            gpu_queue,
+            queue,
             receiver: tile_recv,
+            workers,
+            abort,
+            errors: Vec::new(),
         }
     }
 
-    fn calculate_reference_with(c: V2) -> [[V2<f32>; 2]; ITER_COUNT] {
-        let mut z_values = [[V2::zero(); 2]; ITER_COUNT];
+    fn calculate_reference_with(c: V2) -> [[V2<f32>; 2]; DEFAULT_ITER_COUNT as usize] {
+        let mut z_values = [[V2::zero(); 2]; DEFAULT_ITER_COUNT as usize];
         let mut z = V2::zero();
-        for i in 0..ITER_COUNT {
-            z_values[i][0].x = z.x as f32;
-            z_values[i][0].y = z.y as f32;
+        for v in z_values.iter_mut() {
+            v[0].x = z.x as f32;
+            v[0].y = z.y as f32;
 
             // NOTE: does this even work?, also does it help
-            z_values[i][1].x = (z.x - z_values[i][0].x as f64) as f32;
-            z_values[i][1].y = (z.y - z_values[i][0].y as f64) as f32;
+            v[1].x = (z.x - v[0].x as f64) as f32;
+            v[1].y = (z.y - v[0].y as f64) as f32;
 
             z = V2::new(z.x * z.x - z.y * z.y, 2.0 * z.x * z.y) + c;
         }
@@ -93,7 +364,108 @@ impl TileBuilder {
         z_values
     }
 
-    fn gen_tile(alg: &[FractalStep], p: &TilePos, a: V2) -> Image {
+    /// Build a tile on the cpu, without the extra samples.
+    ///
+    /// The tile is built in passes, see `PASSES`. Every pass is passed to `send`, building stops
+    /// early if that returns false, or when `abort` is set.
+    fn gen_tile(
+        alg: &[FractalStep],
+        trap: &OrbitTrap,
+        iter_count: u32,
+        p: &TilePos,
+        abort: &AtomicBool,
+        mut send: impl FnMut(TileData) -> bool,
+    ) {
+        fn on_grid(x: u32, y: u32, step: u32) -> bool {
+            x.is_multiple_of(step) && y.is_multiple_of(step)
+        }
+
+        let size = 256;
+        let pos = p.square();
+        let min = pos.corner_min();
+        let pixel_size = pos.size().x / size as f64;
+
+        let mut samples = vec![bytemuck::Zeroable::zeroed(); size as usize * size as usize];
+
+        let mut prev_step = None;
+        for step in PASSES {
+            // The pixels on this grid, that were not computed in the previous pass
+            let mut pixels = Vec::new();
+            let mut points = Vec::new();
+            for y in 0..size {
+                for x in 0..size {
+                    if on_grid(x, y, step) && !prev_step.is_some_and(|s| on_grid(x, y, s)) {
+                        pixels.push((x, y));
+                        points.push(min + V2::new(x as f64 + 0.5, y as f64 + 0.5) * pixel_size);
+                    }
+                }
+            }
+
+            // Every computed pixel also covers the pixels of its block, until they are computed
+            let new = Self::gen_samples(alg, trap, iter_count, &points, pixel_size, abort);
+            for ((x, y), s) in pixels.into_iter().zip(new) {
+                for by in y..(y + step).min(size) {
+                    for bx in x..(x + step).min(size) {
+                        samples[(by * size + bx) as usize] = s;
+                    }
+                }
+            }
+            prev_step = Some(step);
+
+            let mut data = TileData::new(V2::new(size, size), samples.clone());
+            data.step = step;
+
+            // The samples are incomplete when aborted
+            if abort.load(Ordering::Relaxed) || !send(data) {
+                return;
+            }
+        }
+    }
+
+    /// Add the extra samples to a complete tile, computed on the cpu
+    fn supersample(
+        alg: &[FractalStep],
+        trap: &OrbitTrap,
+        iter_count: u32,
+        sampling: Sampling,
+        p: &TilePos,
+        abort: &AtomicBool,
+        data: &mut TileData,
+    ) {
+        if sampling == Sampling::Single {
+            return;
+        }
+
+        let pos = p.square();
+        let min = pos.corner_min();
+        let size = data.size;
+        let pixel_size = pos.size().x / size.x as f64;
+
+        let supersampled = sampling.supersample(data, |pixels, offset| {
+            let points = pixels
+                .iter()
+                .map(|i| {
+                    let corner = V2::new((i % size.x) as f64, (i / size.x) as f64);
+                    min + (corner + offset) * pixel_size
+                })
+                .collect::<Vec<_>>();
+            let samples = Self::gen_samples(alg, trap, iter_count, &points, pixel_size, abort);
+            (!abort.load(Ordering::Relaxed)).then_some(samples)
+        });
+        data.supersampled = supersampled;
+    }
+
+    /// Run the kernel for every point, `pixel_size` is the unit of the distance estimates.
+    ///
+    /// When `abort` is set this returns early, with only part of the samples computed.
+    fn gen_samples(
+        alg: &[FractalStep],
+        trap: &OrbitTrap,
+        iter_count: u32,
+        points: &[V2],
+        pixel_size: f64,
+        abort: &AtomicBool,
+    ) -> Vec<Sample> {
         fn cpx_sqr(z: V2) -> V2 {
             V2 {
                 x: z.x * z.x - z.y * z.y,
@@ -115,71 +487,199 @@ impl TileBuilder {
             }
         }
 
-        // the sin() and log2() can be optimized
-        let size = 256;
-        let mut data = vec![0_u8; size as usize * size as usize * 4];
+        fn cpx_mul(a: V2, b: V2) -> V2 {
+            V2 {
+                x: a.x * b.x - a.y * b.y,
+                y: a.x * b.y + a.y * b.x,
+            }
+        }
 
-        let pos = p.square();
+        fn cpx_div(a: V2, b: V2) -> V2 {
+            let d = b.magnitude2();
+            V2 {
+                x: (a.x * b.x + a.y * b.y) / d,
+                y: (a.y * b.x - a.x * b.y) / d,
+            }
+        }
 
-        let min = pos.corner_min();
-        let max = pos.corner_max();
+        /// Interior distance estimate in world units, for a point with an attracting cycle
+        /// of length `period` that goes through approximately `z0`.
+        fn interior_distance(alg: &[FractalStep], c: V2, mut z0: V2, period: u32) -> f64 {
+            let one = V2::new(1.0, 0.0);
+
+            // derivatives after one period, with respect to z0 and c
+            let cycle = |z0: V2| {
+                let mut z = z0;
+                let mut d_z = one;
+                let mut d_c = V2::zero();
+                let mut d_zz = V2::zero();
+                let mut d_zc = V2::zero();
+
+                for _ in 0..period {
+                    for s in alg.iter() {
+                        match s {
+                            FractalStep::Conj => {
+                                z.y = -z.y;
+                                for v in [&mut d_z, &mut d_c, &mut d_zz, &mut d_zc] {
+                                    v.y = -v.y;
+                                }
+                            }
+                            FractalStep::AbsR => {
+                                if z.x < 0.0 {
+                                    z.x = -z.x;
+                                    for v in [&mut d_z, &mut d_c, &mut d_zz, &mut d_zc] {
+                                        v.x = -v.x;
+                                    }
+                                }
+                            }
+                            FractalStep::AbsI => {
+                                if z.y > 0.0 {
+                                    z.y = -z.y;
+                                    for v in [&mut d_z, &mut d_c, &mut d_zz, &mut d_zc] {
+                                        v.y = -v.y;
+                                    }
+                                }
+                            }
+                            FractalStep::Square => {
+                                d_zz = (cpx_sqr(d_z) + cpx_mul(z, d_zz)) * 2.0;
+                                d_zc = (cpx_mul(d_z, d_c) + cpx_mul(z, d_zc)) * 2.0;
+                                d_z = cpx_mul(z, d_z) * 2.0;
+                                d_c = cpx_mul(z, d_c) * 2.0;
+                                z = cpx_sqr(z);
+                            }
+                            FractalStep::Cube => {
+                                let z2 = cpx_sqr(z);
+                                d_zz = cpx_mul(z, cpx_sqr(d_z)) * 6.0 + cpx_mul(z2, d_zz) * 3.0;
+                                d_zc =
+                                    cpx_mul(z, cpx_mul(d_z, d_c)) * 6.0 + cpx_mul(z2, d_zc) * 3.0;
+                                d_z = cpx_mul(z2, d_z) * 3.0;
+                                d_c = cpx_mul(z2, d_c) * 3.0;
+                                z = cpx_cube(z);
+                            }
+                            FractalStep::AddC => {
+                                z += c;
+                                d_c.x += 1.0;
+                            }
+                        }
+                    }
+                }
+                (z, d_z, d_c, d_zz, d_zc)
+            };
 
-        let center = min * 0.5 + max * 0.5;
+            // the detected point is only close to the cycle, refine it with newton
+            for _ in 0..4 {
+                let (z, d_z, ..) = cycle(z0);
+                z0 -= cpx_div(z - z0, d_z - one);
+            }
 
-        let mut values = Vec::with_capacity(size as usize * size as usize);
+            let (_, d_z, d_c, d_zz, d_zc) = cycle(z0);
+            let de = (1.0 - d_z.magnitude2())
+                / (d_zc + cpx_div(cpx_mul(d_zz, d_c), one - d_z)).magnitude();
+            de.max(0.0)
+        }
 
-        // img -> iter -> type
-        // or
-        // iter -> type -> img?
-        for y in 0..size {
-            for x in 0..size {
-                let i = (y * size + x) as u32;
+        // State of a single pixel while iterating
+        struct Point {
+            i: u32,
+            c: V2,
+            z: V2,
 
-                let px = (x as f64 + 0.5) / (size) as f64;
-                let py = (y as f64 + 0.5) / (size) as f64;
+            // derivative dz/dc
+            dz: V2,
 
-                let x = min.x as f64 * (1.0 - px) + max.x as f64 * px;
-                let y = min.y as f64 * (1.0 - py) + max.y as f64 * py;
+            // closest distance to the orbit trap, and when it happened
+            trap_dist: f64,
+            trap_iter: f64,
 
-                let c: V2<f64> = V2::new(x, y);
-                let z: V2<f64> = V2::zero();
-                values.push((i, c, z));
-            }
+            // smallest |z|^2 and at which iteration
+            atom_dist: f64,
+            atom: u32,
+
+            // if the orbit comes back to this point it is periodic
+            z_ref: V2,
+            z_ref_iter: u32,
+        }
+
+        // squared distance at which we consider an orbit periodic
+        let period_epsilon = 1e-24;
+
+        let interior = Sample {
+            iter: -1.0,
+            de: 0.0,
+            normal: 0.0,
+            trap_dist: 0.0,
+            trap_iter: 0.0,
+            z_abs: 0.0,
+            period: 0.0,
+            atom: 0.0,
+        };
+        let mut data = vec![interior; points.len()];
+
+        let mut values = Vec::with_capacity(points.len());
+        for (i, c) in points.iter().enumerate() {
+            values.push(Point {
+                i: i as u32,
+                c: *c,
+                z: V2::zero(),
+                dz: V2::zero(),
+                trap_dist: f64::INFINITY,
+                trap_iter: 0.0,
+                atom_dist: f64::INFINITY,
+                atom: 0,
+                z_ref: V2::zero(),
+                z_ref_iter: 0,
+            });
         }
 
+        // dz is the derivative dz/dc, the non analytic steps (abs, conj) only
+        // flip the sign of one of its components, just like they do for z.
         let mut t = 0.0;
-        for _ in 0..ITER_COUNT {
+        for n in 1..iter_count + 1 {
+            if abort.load(Ordering::Relaxed) {
+                break;
+            }
+
             for s in alg.iter() {
                 let it = values.iter_mut();
                 match s {
                     FractalStep::Conj => {
-                        for (_, _, z) in it {
-                            z.y = -z.y;
+                        for p in it {
+                            p.z.y = -p.z.y;
+                            p.dz.y = -p.dz.y;
                         }
                     }
                     FractalStep::AbsR => {
-                        for (_, _, z) in it {
-                            z.x = z.x.abs()
+                        for p in it {
+                            if p.z.x < 0.0 {
+                                p.z.x = -p.z.x;
+                                p.dz.x = -p.dz.x;
+                            }
                         }
                     }
                     FractalStep::AbsI => {
-                        for (_, _, z) in it {
-                            z.y = -z.y.abs()
+                        for p in it {
+                            if p.z.y > 0.0 {
+                                p.z.y = -p.z.y;
+                                p.dz.y = -p.dz.y;
+                            }
                         }
                     }
                     FractalStep::Square => {
-                        for (_, _, z) in it {
-                            *z = cpx_sqr(*z)
+                        for p in it {
+                            p.dz = cpx_mul(p.z, p.dz) * 2.0;
+                            p.z = cpx_sqr(p.z);
                         }
                     }
                     FractalStep::Cube => {
-                        for (_, _, z) in it {
-                            *z = cpx_cube(*z)
+                        for p in it {
+                            p.dz = cpx_mul(cpx_sqr(p.z), p.dz) * 3.0;
+                            p.z = cpx_cube(p.z);
                         }
                     }
                     FractalStep::AddC => {
-                        for (_, c, z) in values.iter_mut() {
-                            *z = *z + *c;
+                        for p in it {
+                            p.z += p.c;
+                            p.dz.x += 1.0;
                         }
 
                         t += 1.0;
@@ -188,95 +688,290 @@ impl TileBuilder {
             }
 
             for ii in (0..values.len()).rev() {
-                let (i, c, z) = unsafe { values.get_unchecked(ii) };
-                let i = *i;
+                let p = unsafe { values.get_unchecked_mut(ii) };
+                let z = p.z;
                 let d = z.x * z.x + z.y * z.y;
 
+                let trap_dist = trap.distance(z);
+                if trap_dist < p.trap_dist {
+                    p.trap_dist = trap_dist;
+                    p.trap_iter = t;
+                }
+
+                if d < p.atom_dist {
+                    p.atom_dist = d;
+                    p.atom = n;
+                }
+
                 if d > 256.0 {
-                    // apply smooth coloring
+                    // smooth escape time, the coloring is applied later
                     let t = t - d.log2().log2() + 4.0;
 
-                    let pi3 = std::f64::consts::FRAC_PI_3;
-                    let a = (1.0 - (t / (1024.0)).powi(2)).min(1.0).max(0.0);
-                    let t = t * 0.005;
+                    // exterior distance estimate, in pixels
+                    let z_abs = d.sqrt();
+                    let de = z_abs * z_abs.ln() / p.dz.magnitude() / pixel_size;
 
-                    // compute r,g,b rainbow color values from the fractal escape time
-                    let r = a * ((0.5 - t) * 3.0 * pi3 + pi3 * 0.0).sin();
-                    let g = a * ((0.5 - t) * 3.0 * pi3 + pi3 * 1.0).sin();
-                    let b = a * ((0.5 - t) * 3.0 * pi3 + pi3 * 2.0).sin();
+                    // direction of z/dz
+                    let u = cpx_mul(z, V2::new(p.dz.x, -p.dz.y));
+                    let normal = u.y.atan2(u.x);
 
-                    let r = r * r;
-                    let g = g * g;
-                    let b = b * b;
+                    unsafe {
+                        *data.get_unchecked_mut(p.i as usize) = Sample {
+                            iter: t as f32,
+                            de: de as f32,
+                            normal: normal as f32,
+                            trap_dist: p.trap_dist as f32,
+                            trap_iter: p.trap_iter as f32,
+                            z_abs: z_abs as f32,
+                            period: 0.0,
+                            atom: p.atom as f32,
+                        };
+                    }
+                    values.swap_remove(ii);
+                    continue;
+                }
+
+                if (z - p.z_ref).magnitude2() < period_epsilon {
+                    // We are in a cycle, so this point will never escape.
+                    // Everything the orbit is going to visit, has already been visited.
+                    let period = n - p.z_ref_iter;
+                    let de = interior_distance(alg, p.c, z, period) / pixel_size;
 
                     unsafe {
-                        *data.get_unchecked_mut(i as usize * 4 + 0) = (r * 255.0) as _;
-                        *data.get_unchecked_mut(i as usize * 4 + 1) = (g * 255.0) as _;
-                        *data.get_unchecked_mut(i as usize * 4 + 2) = (b * 255.0) as _;
-                        *data.get_unchecked_mut(i as usize * 4 + 3) = 255;
+                        *data.get_unchecked_mut(p.i as usize) = Sample {
+                            iter: -1.0,
+                            de: de as f32,
+                            normal: 0.0,
+                            trap_dist: p.trap_dist as f32,
+                            trap_iter: p.trap_iter as f32,
+                            z_abs: d.sqrt() as f32,
+                            period: period as f32,
+                            atom: p.atom as f32,
+                        };
                     }
                     values.swap_remove(ii);
+                    continue;
+                }
+
+                // Move the reference point further along, every power of two
+                if n.is_power_of_two() {
+                    p.z_ref = z;
+                    p.z_ref_iter = n;
                 }
             }
         }
 
-        Image::new(V2::new(size, size), data)
+        // These never escaped, but we also did not find a cycle
+        for p in values.iter() {
+            let s = &mut data[p.i as usize];
+            s.trap_dist = p.trap_dist as f32;
+            s.trap_iter = p.trap_iter as f32;
+            s.z_abs = p.z.magnitude() as f32;
+            s.atom = p.atom as f32;
+        }
+
+        data
     }
 
-    /// Either return a cached tile, or add it to the build queue
-    pub fn tile(&mut self, p: &TilePos) -> Option<&Image> {
-        let in_cache = self.cache.contains_key(p);
+    /// Make sure this tile is built, lower priorities are built first.
+    ///
+    /// This has to be called every frame, until the tile is built.
+    pub fn request(&mut self, p: &TilePos, priority: f64) {
+        self.requested.insert(*p);
 
-        if !in_cache {
-            let result = if p.z < 16 {
-                self.gpu_sender.try_send(*p).map_err(|_| ())
-            } else {
-                self.sender.try_send((*p, V2::zero())).map_err(|_| ())
-            };
+        // This also updates the priority of queued tiles
+        if self.cache.request(p) {
+            self.queue_of(p).push(*p, priority);
+        }
+    }
+
+    /// The queue of the backend that builds this tile
+    fn queue_of(&self, p: &TilePos) -> &TileQueue {
+        if p.z <= self.gpu_max_z {
+            &self.gpu_queue
+        } else {
+            &self.queue
+        }
+    }
+
+    /// Return a cached tile, but don't build it if it is missing
+    pub fn tile(&mut self, p: &TilePos) -> Option<&mut Tile> {
+        self.cache.get(p)
+    }
+
+    pub fn has_tile(&self, p: &TilePos) -> bool {
+        self.cache.contains(p)
+    }
+
+    /// Stop building, the tiles that are already built stay available.
+    ///
+    /// Queued tiles are dropped, the workers exit after finishing their current tile.
+    /// Those tiles are still received by `update`. This does not block, see `join`.
+    pub fn shutdown(&self) {
+        self.gpu_queue.close();
+        self.queue.close();
+    }
 
-            // tell a builder to build this tile
-            if let Ok(_) = result {
-                // Tile is queued, don't request it again
-                self.cache.insert(*p, None);
+    /// Shut down and wait for all workers to exit.
+    ///
+    /// The tiles that were being built are finished and added to the cache.
+    /// Returns the errors of all workers that panicked.
+    pub fn join(&mut self) -> Result<(), Vec<String>> {
+        self.shutdown();
+
+        // Keep receiving, the workers could be waiting to send their last tile
+        while !self.workers.iter().all(Worker::is_finished) {
+            if let Ok((p, data)) = self.receiver.recv_timeout(Duration::from_millis(10)) {
+                self.receive(p, data);
             }
+        }
 
-            None
-        } else {
-            let cache_entry = self.cache.get_mut(p).unwrap();
-            match cache_entry {
-                // The tile was cached
-                Some((img, count)) => {
-                    *count += 1;
-                    return Some(img);
-                }
+        while let Ok((p, data)) = self.receiver.try_recv() {
+            self.receive(p, data);
+        }
 
-                // The tile is already queud, just not done yet
-                None => return None,
-            };
+        for w in self.workers.iter_mut() {
+            if let Err(e) = w.join() {
+                self.errors.push(e);
+            }
+        }
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors.clone())
         }
     }
 
-    /// update the cache, removing unused tiles and inserting newly finished tiles
+    /// Workers that stopped unexpectedly
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    /// Number of tiles waiting in the queues
+    pub fn queued(&self) -> usize {
+        self.gpu_queue.len() + self.queue.len()
+    }
+
+    /// Throughput of every worker, in the order they were started
+    pub fn worker_metrics(&self) -> Vec<WorkerMetrics> {
+        self.workers.iter().map(Worker::metrics).collect()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats
+    }
+
+    pub fn set_cache_budget(&mut self, budget: usize) {
+        self.cache.budget = budget;
+    }
+
+    /// update the cache, evicting old tiles and inserting newly finished tiles
     pub fn update(&mut self) {
-        let mut new_cache = BTreeMap::new();
+        // Cancel tiles that are no longer wanted, unless a worker already started on them
+        for p in self.cache.pending() {
+            if !self.requested.contains(&p) && self.queue_of(&p).cancel(&p) {
+                self.cache.remove(&p);
+            }
+        }
 
-        for (k, v) in std::mem::take(&mut self.cache) {
-            match v {
-                Some((img, cnt)) if cnt > 0 => {
-                    new_cache.insert(k, Some((img, 0)));
+        // Check for finished tiles
+        while let Ok((p, data)) = self.receiver.try_recv() {
+            self.receive(p, data);
+        }
+
+        // Workers only exit by themselves when they panic
+        for w in self.workers.iter_mut().filter(|w| w.handle.is_some()) {
+            if w.is_finished() {
+                if let Err(e) = w.join() {
+                    eprintln!("{}", e);
+                    self.errors.push(e);
                 }
-                None => {
-                    new_cache.insert(k, None);
+            }
+        }
+
+        self.cache.update();
+        self.requested.clear();
+    }
+
+    /// Insert a newly built tile, a refinement pass replaces the previous pass
+    fn receive(&mut self, p: TilePos, data: TileData) {
+        // It could have been queued again between finishing and arriving here
+        if data.is_complete() {
+            self.queue_of(&p).cancel(&p);
+        }
+
+        let received = match self.cache.get(&p) {
+            Some(tile) => tile.received,
+            None => Instant::now(),
+        };
+
+        let tile = Tile {
+            data,
+            image: None,
+            received,
+        };
+        self.cache.insert(p, tile);
+    }
+}
+
+impl Drop for TileBuilder {
+    /// Stops all workers as soon as possible. They are joined on another thread, so dropping the
+    /// builder does not hold up the frame, and still no threads are leaked.
+    fn drop(&mut self) {
+        self.abort.store(true, Ordering::Relaxed);
+        self.shutdown();
+
+        // A worker waiting to send its tile stops once the receiver is dropped, after this.
+        // Errors found by `update` are already reported, those workers are joined.
+        let mut workers = std::mem::take(&mut self.workers);
+        let result = std::thread::Builder::new()
+            .name("worker reaper".to_string())
+            .spawn(move || {
+                for w in workers.iter_mut() {
+                    if let Err(e) = w.join() {
+                        eprintln!("{}", e);
+                    }
                 }
-                _ => (),
-            };
+            });
+        if let Err(e) = result {
+            eprintln!("Could not start joining the workers: {}", e);
         }
+    }
+}
 
-        // Check for finished tiles
-        while let Ok((p, i)) = self.receiver.try_recv() {
-            new_cache.insert(p, Some((i, 1)));
+/// Lower the priority of the calling thread, so the workers don't compete with the ui and the
+/// rest of the system. Only supported on linux, elsewhere this does nothing.
+fn lower_thread_priority() {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        // On linux the nice value is per thread, so this does not affect the main thread
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        if libc::setpriority(libc::PRIO_PROCESS, tid, 10) != 0 {
+            eprintln!("Could not lower the worker priority");
         }
+    }
+}
+
+#[test]
+fn test_worker_panic() {
+    let queue = Arc::new(TileQueue::new());
+    let p = TilePos { x: 0, y: 0, z: 20 };
+    queue.push(p, 1.0);
 
-        self.cache = new_cache;
+    let name = "test worker".to_string();
+    let mut worker = Worker::spawn(name, false, &queue, |stats, queue| {
+        if stats.pop(queue).is_some() {
+            panic!("test panic");
+        }
+    });
+    while !worker.is_finished() {
+        std::thread::yield_now();
     }
+    let error = "test worker panicked: test panic".to_string();
+    assert_eq!(worker.join(), Err(error));
+
+    // The tile is no longer building, so it can be queued again
+    queue.push(p, 1.0);
+    assert_eq!(queue.len(), 1);
 }
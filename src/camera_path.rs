@@ -0,0 +1,257 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::builder::DEFAULT_ITER_COUNT;
+use crate::util::*;
+use crate::viewport::Location;
+
+/// Time between keyframes added at the end of the path, in seconds
+pub const KEYFRAME_SPACING: f64 = 2.0;
+
+/// Above this zoom difference between two keyframes, the position follows the zoom
+const ZOOM_FOLLOW: f64 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds since the start of the path
+    pub time: f64,
+
+    pub location: Location,
+
+    /// Shift of the palette, see `Coloring::palette_offset`
+    pub palette_offset: f64,
+
+    /// Maximum number of iterations, deeper views need more
+    #[serde(default = "default_iter_count")]
+    pub iter_count: u32,
+}
+
+fn default_iter_count() -> u32 {
+    DEFAULT_ITER_COUNT
+}
+
+/// Keyframes over time, interpolated with a spline
+///
+/// The zoom, rotation and palette use a Catmull-Rom spline, that starts and stops smoothly.
+/// The position follows the same spline, unless two keyframes differ a lot in zoom. Then the
+/// position moves in proportion to the width of the view, so the point we zoom into stays on the
+/// screen. Otherwise it would only arrive when we are already deep in.
+///
+/// The iteration count is interpolated geometrically, and rounded to a power of two. Every
+/// change rebuilds all tiles, so it should not change every frame.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    /// Sorted by time
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> f64 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    /// Add a keyframe, replacing the keyframe at the same time if there is one
+    pub fn insert(&mut self, key: Keyframe) {
+        let i = self.keyframes.partition_point(|k| k.time < key.time);
+        match self.keyframes.get_mut(i) {
+            Some(k) if k.time == key.time => *k = key,
+            _ => self.keyframes.insert(i, key),
+        }
+    }
+
+    /// Add a keyframe after the last one
+    pub fn push(&mut self, location: Location, palette_offset: f64, iter_count: u32) {
+        let time = match self.keyframes.last() {
+            Some(k) => k.time + KEYFRAME_SPACING,
+            None => 0.0,
+        };
+
+        self.insert(Keyframe {
+            time,
+            location,
+            palette_offset,
+            iter_count,
+        });
+    }
+
+    pub fn pop(&mut self) -> Option<Keyframe> {
+        self.keyframes.pop()
+    }
+
+    /// Remove the keyframe at this time, if there is one
+    pub fn remove(&mut self, time: f64) -> Option<Keyframe> {
+        let i = self.keyframes.iter().position(|k| k.time == time)?;
+        Some(self.keyframes.remove(i))
+    }
+
+    /// Time of the last keyframe before `time`
+    pub fn previous(&self, time: f64) -> Option<f64> {
+        let k = self.keyframes.iter().rev().find(|k| k.time < time)?;
+        Some(k.time)
+    }
+
+    /// Time of the first keyframe after `time`
+    pub fn next(&self, time: f64) -> Option<f64> {
+        let k = self.keyframes.iter().find(|k| k.time > time)?;
+        Some(k.time)
+    }
+
+    /// The interpolated state at `time`, None if there are no keyframes
+    pub fn at(&self, time: f64) -> Option<Keyframe> {
+        let keys = &self.keyframes;
+        let first = keys.first()?;
+        let last = keys.last()?;
+        if time <= first.time {
+            return Some(*first);
+        }
+        if time >= last.time {
+            return Some(*last);
+        }
+
+        // The segment we are in, from k0 to k1
+        let i = keys.partition_point(|k| k.time <= time) - 1;
+        let k0 = &keys[i];
+        let k1 = &keys[i + 1];
+        let dt = k1.time - k0.time;
+        let u = (time - k0.time) / dt;
+
+        // Catmull-Rom tangents in units per second, zero at the ends so the path starts and stops
+        // smoothly. The spline is relative to k0, so deep locations keep their precision.
+        let spline = |values: &[f64]| {
+            let tangent = |j: usize| {
+                if j == 0 || j + 1 == keys.len() {
+                    return 0.0;
+                }
+                (values[j + 1] - values[j - 1]) / (keys[j + 1].time - keys[j - 1].time)
+            };
+
+            let m0 = tangent(i) * dt;
+            let m1 = tangent(i + 1) * dt;
+            values[i] + hermite(values[i + 1] - values[i], m0, m1, u)
+        };
+
+        let values = |f: &dyn Fn(&Keyframe) -> f64| keys.iter().map(f).collect::<Vec<_>>();
+        let zoom = spline(&values(&|k| k.location.zoom));
+        let palette_offset = spline(&values(&|k| k.palette_offset));
+        let rotation = spline(&self.unwrapped_rotations());
+
+        // The spline can overshoot, that would rebuild the tiles twice
+        let iter_log = spline(&values(&|k| (k.iter_count as f64).log2()));
+        let (a, b) = ((k0.iter_count as f64).log2(), (k1.iter_count as f64).log2());
+        let iter_log = iter_log.clamp(a.min(b), a.max(b));
+        let iter_count = iter_log.round().exp2() as u32;
+
+        let p0 = k0.location.offset;
+        let p1 = k1.location.offset;
+        let offset = if (k1.location.zoom - k0.location.zoom).abs() > ZOOM_FOLLOW {
+            let w0 = 0.5_f64.powf(k0.location.zoom);
+            let w1 = 0.5_f64.powf(k1.location.zoom);
+            let w = 0.5_f64.powf(zoom);
+            p0 + (p1 - p0) * ((w - w0) / (w1 - w0))
+        } else {
+            let x = spline(&values(&|k| k.location.offset.x - p0.x));
+            let y = spline(&values(&|k| k.location.offset.y - p0.y));
+            p0 + V2::new(x, y)
+        };
+
+        Some(Keyframe {
+            time,
+            location: Location {
+                offset,
+                zoom,
+                rotation,
+            },
+            palette_offset,
+            iter_count,
+        })
+    }
+
+    /// The rotations of all keyframes, with whole turns added so it never turns more than half
+    /// a turn between two keyframes
+    fn unwrapped_rotations(&self) -> Vec<f64> {
+        let pi = std::f64::consts::PI;
+        let mut result: Vec<f64> = Vec::with_capacity(self.keyframes.len());
+        for k in self.keyframes.iter() {
+            let r = k.location.rotation;
+            let r = match result.last() {
+                Some(prev) => prev + (r - prev + pi).rem_euclid(2.0 * pi) - pi,
+                None => r,
+            };
+            result.push(r);
+        }
+        result
+    }
+}
+
+/// Cubic hermite spline from 0 to `d`, with tangents `m0` and `m1`
+fn hermite(d: f64, m0: f64, m1: f64, u: f64) -> f64 {
+    let u2 = u * u;
+    let u3 = u2 * u;
+    (-2.0 * u3 + 3.0 * u2) * d + (u3 - 2.0 * u2 + u) * m0 + (u3 - u2) * m1
+}
+
+#[cfg(test)]
+fn test_keyframe(time: f64, zoom: f64, iter_count: u32) -> Keyframe {
+    Keyframe {
+        time,
+        location: Location {
+            offset: V2::zero(),
+            zoom,
+            rotation: 0.0,
+        },
+        palette_offset: 0.0,
+        iter_count,
+    }
+}
+
+#[test]
+fn test_insert_remove() {
+    let mut path = CameraPath::default();
+    path.insert(test_keyframe(2.0, 1.0, 1024));
+    path.insert(test_keyframe(0.0, 0.0, 1024));
+    path.insert(test_keyframe(1.0, 0.5, 1024));
+
+    // At the same time the keyframe is replaced
+    path.insert(test_keyframe(1.0, 0.7, 1024));
+    let times = path.keyframes().iter().map(|k| k.time).collect::<Vec<_>>();
+    assert_eq!(times, vec![0.0, 1.0, 2.0]);
+    assert_eq!(path.at(1.0).unwrap().location.zoom, 0.7);
+
+    assert_eq!(path.previous(1.0), Some(0.0));
+    assert_eq!(path.next(1.0), Some(2.0));
+    assert_eq!(path.next(2.0), None);
+
+    assert_eq!(path.remove(1.5), None);
+    assert_eq!(path.remove(1.0).map(|k| k.time), Some(1.0));
+    assert_eq!(path.keyframes().len(), 2);
+    assert_eq!(path.duration(), 2.0);
+}
+
+#[test]
+fn test_iter_count() {
+    let mut path = CameraPath::default();
+    path.insert(test_keyframe(0.0, 0.0, 1024));
+    path.insert(test_keyframe(1.0, 10.0, 16384));
+    path.insert(test_keyframe(2.0, 20.0, 1024));
+
+    assert_eq!(path.at(0.0).unwrap().iter_count, 1024);
+    assert_eq!(path.at(1.0).unwrap().iter_count, 16384);
+
+    // Powers of two in between, that only go up and then down
+    let counts = (0..=200)
+        .map(|i| path.at(i as f64 * 0.01).unwrap().iter_count)
+        .collect::<Vec<_>>();
+    assert!(counts.iter().all(|n| n.is_power_of_two()));
+    assert!(counts[..=100].windows(2).all(|w| w[0] <= w[1]));
+    assert!(counts[100..].windows(2).all(|w| w[0] >= w[1]));
+    assert!(counts.contains(&4096));
+}
@@ -0,0 +1,347 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::builder::Tile;
+use crate::image::Image;
+use crate::tile_data::*;
+
+/// Minimum change in the cumulative distribution before we bother recolouring
+const CDF_TOLERANCE: f32 = 1.0 / 128.0;
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum ColorMode {
+    /// Rainbow palette indexed directly by the escape time
+    Rainbow,
+
+    /// Rainbow palette indexed by the escape time distribution of the visible tiles,
+    /// so the colours are well distributed at any zoom level and iteration count
+    Histogram,
+
+    /// Grayscale by the distance estimate, dark close to the set
+    Distance,
+
+    /// Brightness by the closest distance to the orbit trap, hue by when that happened
+    Trap,
+}
+
+impl ColorMode {
+    pub fn next(self) -> Self {
+        match self {
+            ColorMode::Rainbow => ColorMode::Histogram,
+            ColorMode::Histogram => ColorMode::Distance,
+            ColorMode::Distance => ColorMode::Trap,
+            ColorMode::Trap => ColorMode::Rainbow,
+        }
+    }
+}
+
+/// How the points inside the set are coloured
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum InteriorMode {
+    /// Transparent
+    #[default]
+    None,
+
+    /// Brightness by the final |z|
+    Magnitude,
+
+    /// Hue by the period of the attracting cycle, every hyperbolic component gets one colour
+    Period,
+
+    /// Grayscale by the interior distance estimate, dark close to the boundary
+    Distance,
+
+    /// Hue by the atom domain, the iteration at which the orbit came closest to zero
+    AtomDomain,
+}
+
+impl InteriorMode {
+    pub fn next(self) -> Self {
+        match self {
+            InteriorMode::None => InteriorMode::Magnitude,
+            InteriorMode::Magnitude => InteriorMode::Period,
+            InteriorMode::Period => InteriorMode::Distance,
+            InteriorMode::Distance => InteriorMode::AtomDomain,
+            InteriorMode::AtomDomain => InteriorMode::None,
+        }
+    }
+}
+
+/// Directional light, applied on top of the palette
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Light {
+    pub enabled: bool,
+
+    /// Direction the light is coming from, in radians
+    pub angle: f64,
+
+    /// Height of the light above the surface, higher is a flatter look
+    pub height: f64,
+
+    /// Fraction of the light that does not depend on the direction
+    pub ambient: f64,
+}
+
+impl Light {
+    fn shade(&self, normal: f32) -> f64 {
+        let reflection = ((normal as f64 - self.angle).cos() + self.height) / (1.0 + self.height);
+        self.ambient + (1.0 - self.ambient) * reflection.max(0.0)
+    }
+}
+
+/// Turns tile data into images
+///
+/// Colouring is a separate pass over all visible tiles, so it can depend on the entire view.
+/// Every frame the histograms of the visible tiles are collected with `add`, between `begin`
+/// and `end`. Tiles are only recoloured when the colours actually change.
+pub struct Coloring {
+    pub mode: ColorMode,
+    pub interior: InteriorMode,
+
+    /// Darken pixels closer than a pixel to the set, this outlines the thin filaments
+    pub outline: bool,
+
+    pub light: Light,
+
+    /// Shifts the hues of the palette, one is a full cycle through the palette
+    pub palette_offset: f64,
+
+    /// Escape time histogram of the current frame
+    histogram: Vec<u32>,
+
+    /// Cumulative distribution of the escape times, has one more entry than there are bins
+    cdf: Option<Vec<f32>>,
+
+    /// Incremented every time the colours change, tiles with an older version are recoloured
+    version: u32,
+}
+
+impl Coloring {
+    pub fn new() -> Self {
+        Coloring {
+            mode: ColorMode::Rainbow,
+            interior: InteriorMode::None,
+            outline: false,
+            light: Light {
+                enabled: false,
+                angle: std::f64::consts::FRAC_PI_4,
+                height: 1.5,
+                ambient: 0.2,
+            },
+            palette_offset: 0.0,
+            histogram: vec![0; HISTOGRAM_BINS],
+            cdf: None,
+            version: 0,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: ColorMode) {
+        if mode != self.mode {
+            self.mode = mode;
+            self.version += 1;
+        }
+    }
+
+    pub fn set_interior(&mut self, interior: InteriorMode) {
+        if interior != self.interior {
+            self.interior = interior;
+            self.version += 1;
+        }
+    }
+
+    pub fn set_outline(&mut self, outline: bool) {
+        if outline != self.outline {
+            self.outline = outline;
+            self.version += 1;
+        }
+    }
+
+    pub fn set_light(&mut self, light: Light) {
+        if light != self.light {
+            self.light = light;
+            self.version += 1;
+        }
+    }
+
+    pub fn set_palette_offset(&mut self, offset: f64) {
+        if offset != self.palette_offset {
+            self.palette_offset = offset;
+            self.version += 1;
+        }
+    }
+
+    pub fn begin(&mut self) {
+        self.histogram.iter_mut().for_each(|x| *x = 0);
+    }
+
+    pub fn add(&mut self, data: &TileData) {
+        for (dst, src) in self.histogram.iter_mut().zip(data.histogram.iter()) {
+            *dst += *src;
+        }
+    }
+
+    /// Update the distribution with the histograms collected this frame.
+    ///
+    /// To keep the colours stable while tiles are still streaming in, the distribution is only
+    /// replaced when every visible tile was added, unless we have nothing better yet.
+    pub fn end(&mut self, complete: bool) {
+        if self.mode != ColorMode::Histogram {
+            return;
+        }
+
+        if !complete && self.cdf.is_some() {
+            return;
+        }
+
+        let total = self.histogram.iter().map(|x| *x as u64).sum::<u64>();
+        if total == 0 {
+            return;
+        }
+
+        let mut cdf = Vec::with_capacity(HISTOGRAM_BINS + 1);
+        let mut sum = 0;
+        cdf.push(0.0);
+        for x in self.histogram.iter() {
+            sum += *x as u64;
+            cdf.push((sum as f64 / total as f64) as f32);
+        }
+
+        let changed = match &self.cdf {
+            None => true,
+            Some(old) => old
+                .iter()
+                .zip(cdf.iter())
+                .any(|(a, b)| (a - b).abs() > CDF_TOLERANCE),
+        };
+
+        if changed {
+            self.cdf = Some(cdf);
+            self.version += 1;
+        }
+    }
+
+    /// Return the image for this tile, colouring it if the colours changed
+    pub fn image(&self, tile: &mut Tile) -> Image {
+        if let Some((img, version)) = &tile.image {
+            if *version == self.version {
+                return img.clone();
+            }
+        }
+
+        let mut data = vec![0_u8; tile.data.samples.len() * 4];
+        for (dst, s) in data.chunks_exact_mut(4).zip(tile.data.samples.iter()) {
+            dst.copy_from_slice(&self.color(s));
+        }
+
+        // Supersampled pixels get the average colour of their samples
+        if let Some(supersampled) = &tile.data.supersampled {
+            for (j, i) in supersampled.pixels.iter().enumerate() {
+                let mut sum = [0_u32; 4];
+                let mut count = 0;
+                for s in supersampled.pixel_samples(j) {
+                    for (dst, c) in sum.iter_mut().zip(self.color(s)) {
+                        *dst += c as u32;
+                    }
+                    count += 1;
+                }
+
+                let i = *i as usize * 4;
+                let color = sum.map(|s| (s / count.max(1)) as u8);
+                data[i..i + 4].copy_from_slice(&color);
+            }
+        }
+
+        let img = Image::new(tile.data.size, data);
+        tile.image = Some((img.clone(), self.version));
+        img
+    }
+
+    fn color(&self, s: &Sample) -> [u8; 4] {
+        // The trap also colors the points inside the set
+        if self.mode == ColorMode::Trap {
+            let a = (-s.trap_dist as f64 * 8.0).exp();
+            return rainbow(s.trap_iter as f64 * 0.02 + self.palette_offset, a);
+        }
+
+        if !s.escaped() {
+            return self.color_interior(s);
+        }
+
+        let t = s.iter as f64;
+        let mut color = match (self.mode, &self.cdf) {
+            (ColorMode::Histogram, Some(cdf)) => {
+                let bin = histogram_bin(s.iter);
+                let i = bin.floor() as usize;
+                let f = bin - i as f32;
+                let p = (cdf[i] * (1.0 - f) + cdf[i + 1] * f) as f64;
+                rainbow(p + self.palette_offset, 1.0 - p * p)
+            }
+            (ColorMode::Distance, _) => {
+                // the distance spans many orders of magnitude
+                let v = ((s.de as f64).ln_1p() / 6.0).min(1.0);
+                let v = (v * 255.0) as u8;
+                [v, v, v, 255]
+            }
+            _ => {
+                let a = (1.0 - (t / 1024.0).powi(2)).clamp(0.0, 1.0);
+                rainbow(t * 0.005 + self.palette_offset, a)
+            }
+        };
+
+        if self.light.enabled {
+            let a = self.light.shade(s.normal);
+            for c in color.iter_mut().take(3) {
+                *c = (*c as f64 * a).min(255.0) as u8;
+            }
+        }
+
+        if self.outline && s.de < 1.0 {
+            let a = s.de.max(0.0);
+            for c in color.iter_mut().take(3) {
+                *c = (*c as f32 * a) as u8;
+            }
+        }
+
+        color
+    }
+
+    fn color_interior(&self, s: &Sample) -> [u8; 4] {
+        // spread the hues of consecutive integers as far apart as possible
+        let golden = |x: f32| rainbow(x as f64 * 0.618_034, 1.0);
+
+        match self.interior {
+            InteriorMode::None => [0; 4],
+            InteriorMode::Magnitude => {
+                let v = (s.z_abs.min(2.0) / 2.0 * 255.0) as u8;
+                [v, v, v, 255]
+            }
+            InteriorMode::Period if s.period > 0.0 => golden(s.period),
+            InteriorMode::Distance if s.period > 0.0 => {
+                let v = ((s.de as f64).ln_1p() / 6.0).min(1.0);
+                let v = (v * 255.0) as u8;
+                [v, v, v, 255]
+            }
+            InteriorMode::AtomDomain => golden(s.atom),
+
+            // no cycle was found, so we know nothing
+            _ => [0, 0, 0, 255],
+        }
+    }
+}
+
+/// Rainbow palette, repeats every 1.0, `a` is the brightness
+fn rainbow(t: f64, a: f64) -> [u8; 4] {
+    let pi3 = std::f64::consts::FRAC_PI_3;
+
+    // compute r,g,b rainbow color values
+    let r = a * ((0.5 - t) * 3.0 * pi3 + pi3 * 0.0).sin();
+    let g = a * ((0.5 - t) * 3.0 * pi3 + pi3 * 1.0).sin();
+    let b = a * ((0.5 - t) * 3.0 * pi3 + pi3 * 2.0).sin();
+
+    [
+        (r * r * 255.0) as u8,
+        (g * g * 255.0) as u8,
+        (b * b * 255.0) as u8,
+        255,
+    ]
+}
@@ -31,7 +31,7 @@ pub struct Debug {
 }
 
 thread_local! {
-    static STACK: RefCell<Vec<(&'static str, Instant)>> = RefCell::new(Vec::new());
+    static STACK: RefCell<Vec<(&'static str, Instant)>> = const { RefCell::new(Vec::new()) };
 }
 
 use std::sync::Mutex;
@@ -121,6 +121,6 @@ impl Debug {
 
     pub fn print(&mut self, s: &str) {
         self.info.push_str(s);
-        self.info.push_str("\n");
+        self.info.push('\n');
     }
 }
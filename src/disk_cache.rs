@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crossbeam_channel::bounded;
+use crossbeam_channel::Sender;
+
+use crate::tile_data::Sample;
+use crate::tile_data::TileData;
+use crate::tilemap::TilePos;
+use crate::util::*;
+
+/// Where the tiles are stored, every fractal definition gets its own directory in here
+pub const DISK_CACHE_DIR: &str = ".fractal-toy/tiles";
+
+/// Default size limit of the disk cache, in bytes
+pub const DEFAULT_DISK_CACHE_LIMIT: u64 = 2 * 1024 * 1024 * 1024;
+
+/// Change this when the kernels or the tile format change, to invalidate all cached tiles
+const FORMAT_VERSION: u32 = 2;
+
+const MAGIC: &[u8; 4] = b"FTT1";
+
+/// Finished tiles stored on disk, so they survive a restart
+///
+/// Tiles are stored per fractal definition, so a changed formula never returns old tiles.
+/// Only the raw samples are stored, the colouring is applied later just like for new tiles.
+///
+/// This is shared between the builder threads. Files are written to a temporary file first and
+/// then renamed, so a reader never sees a partially written tile.
+pub struct DiskCache {
+    /// Directory for the current fractal definition
+    dir: PathBuf,
+
+    /// Size limit of all definitions together, the least recently used tiles are removed first
+    limit: u64,
+
+    /// Approximate size of the cache, counted again by every cleanup
+    size: AtomicU64,
+
+    /// Asks the cleanup thread to clean up, at most one request is waiting
+    cleanup: Sender<()>,
+}
+
+impl DiskCache {
+    /// `definition` should describe everything the samples depend on, it is hashed to a directory
+    ///
+    /// The cache is cleaned up on a separate thread, looking at all files can take a while. That
+    /// thread also counts the size of the cache first, and exits when the cache is dropped.
+    pub fn new(definition: &str, limit: u64) -> Arc<Self> {
+        let hash = fnv1a(format!("{}\n{}", FORMAT_VERSION, definition).as_bytes());
+        let dir = Path::new(DISK_CACHE_DIR).join(format!("{:016x}", hash));
+
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            eprintln!("Could not create the disk cache at {:?}: {}", dir, e);
+        }
+
+        // Makes it possible to see what is cached where
+        let _ = std::fs::write(dir.join("definition.txt"), definition);
+
+        let (cleanup, requests) = bounded(1);
+        let cache = Arc::new(DiskCache {
+            dir,
+            limit,
+            size: AtomicU64::new(0),
+            cleanup,
+        });
+
+        // The thread does not keep the cache alive, dropping it closes the channel
+        let _ = cache.cleanup.try_send(());
+        let thread_cache = Arc::downgrade(&cache);
+        let result = std::thread::Builder::new()
+            .name("disk cache cleanup".to_string())
+            .spawn(move || {
+                while requests.recv().is_ok() {
+                    match thread_cache.upgrade() {
+                        Some(cache) => cache.clean(),
+                        None => break,
+                    }
+                }
+            });
+        if let Err(e) = result {
+            eprintln!("Could not start the disk cache cleanup: {}", e);
+        }
+        cache
+    }
+
+    fn path(&self, p: &TilePos) -> PathBuf {
+        self.dir.join(format!("{}_{}_{}.tile", p.z, p.x, p.y))
+    }
+
+    /// Load a tile, returns None if it is not cached or unreadable
+    pub fn load(&self, p: &TilePos) -> Option<TileData> {
+        let path = self.path(p);
+        let mut bytes = Vec::new();
+        File::open(&path).ok()?.read_to_end(&mut bytes).ok()?;
+
+        // Mark as recently used
+        if let Ok(file) = File::options().write(true).open(&path) {
+            let _ = file.set_modified(std::time::SystemTime::now());
+        }
+
+        decode(&bytes)
+    }
+
+    /// Store a finished tile, errors are only reported, the cache is optional after all
+    pub fn store(&self, p: &TilePos, data: &TileData) {
+        let bytes = encode(data);
+        let path = self.path(p);
+        // Every tile is built by one thread at a time, but other instances could share the cache
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+
+        let result = File::create(&tmp)
+            .and_then(|mut f| f.write_all(&bytes))
+            .and_then(|_| std::fs::rename(&tmp, &path));
+
+        if let Err(e) = result {
+            eprintln!("Could not store {:?} in the disk cache: {}", p, e);
+            let _ = std::fs::remove_file(&tmp);
+            return;
+        }
+
+        let size = self.size.fetch_add(bytes.len() as u64, Ordering::Relaxed) + bytes.len() as u64;
+        if size > self.limit {
+            // A full channel means a cleanup is already coming
+            let _ = self.cleanup.try_send(());
+        }
+    }
+
+    /// Remove the least recently used tiles of all definitions until we are well within the
+    /// limit again, and count the size again
+    fn clean(&self) {
+        let before = self.size.load(Ordering::Relaxed);
+        let mut files = Vec::new();
+        let dirs = std::fs::read_dir(DISK_CACHE_DIR)
+            .into_iter()
+            .flatten()
+            .flatten();
+        for dir in dirs {
+            for file in std::fs::read_dir(dir.path())
+                .into_iter()
+                .flatten()
+                .flatten()
+            {
+                let path = file.path();
+                if !is_tile(&path) {
+                    continue;
+                }
+
+                if let Ok(meta) = file.metadata() {
+                    let time = meta.modified().unwrap_or(std::time::UNIX_EPOCH);
+                    files.push((time, meta.len(), path));
+                }
+            }
+        }
+
+        let mut size = files.iter().map(|f| f.1).sum::<u64>();
+
+        // Leave some room, so we don't clean up after every tile
+        if size > self.limit {
+            files.sort();
+            for (_, len, path) in files {
+                if size <= self.limit / 4 * 3 {
+                    break;
+                }
+
+                if std::fs::remove_file(&path).is_ok() {
+                    size -= len;
+                }
+            }
+            self.remove_empty_definitions();
+        }
+
+        // Only this thread lowers the size, stores keep adding to it while we count. Those tiles
+        // may be counted twice until the next cleanup, that is only a little early.
+        let (order, recount) = (Ordering::Relaxed, |s: u64| Some(s - before + size));
+        let _ = self.size.fetch_update(order, order, recount);
+    }
+
+    /// Remove the directories of other definitions that have no tiles left
+    fn remove_empty_definitions(&self) {
+        for dir in std::fs::read_dir(DISK_CACHE_DIR)
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            let path = dir.path();
+            let has_tiles = std::fs::read_dir(&path)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .any(|f| is_tile(&f.path()));
+            if !has_tiles && path != self.dir {
+                let _ = std::fs::remove_dir_all(&path);
+            }
+        }
+    }
+}
+
+fn encode(data: &TileData) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&data.size.x.to_le_bytes());
+    bytes.extend_from_slice(&data.size.y.to_le_bytes());
+    bytes.extend_from_slice(bytemuck::cast_slice(&data.samples));
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Option<TileData> {
+    let (magic, bytes) = split(bytes, 4)?;
+    if magic != MAGIC {
+        return None;
+    }
+
+    let (w, bytes) = split(bytes, 4)?;
+    let (h, bytes) = split(bytes, 4)?;
+    let w = u32::from_le_bytes(w.try_into().ok()?);
+    let h = u32::from_le_bytes(h.try_into().ok()?);
+
+    let sample_size = std::mem::size_of::<Sample>();
+    let (samples, bytes) = split(bytes, w as usize * h as usize * sample_size)?;
+    if !bytes.is_empty() {
+        return None;
+    }
+
+    // The file is not aligned, so copy instead of cast
+    let samples: Vec<Sample> = bytemuck::pod_collect_to_vec(samples);
+    Some(TileData::new(V2::new(w, h), samples))
+}
+
+fn is_tile(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "tile")
+}
+
+fn split(bytes: &[u8], n: usize) -> Option<(&[u8], &[u8])> {
+    if bytes.len() < n {
+        return None;
+    }
+    Some(bytes.split_at(n))
+}
+
+/// Hash that does not change between runs or compiler versions
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[test]
+fn test_encode_decode() {
+    let samples = (0..6)
+        .map(|i| {
+            let mut s: Sample = bytemuck::Zeroable::zeroed();
+            s.iter = i as f32 * 1.5;
+            s.de = -(i as f32);
+            s.period = 3.0;
+            s
+        })
+        .collect::<Vec<_>>();
+    let data = TileData::new(V2::new(3, 2), samples.clone());
+
+    let bytes = encode(&data);
+    let decoded = decode(&bytes).unwrap();
+    assert_eq!(decoded.size, data.size);
+    assert_eq!(
+        bytemuck::cast_slice::<Sample, u8>(&decoded.samples),
+        bytemuck::cast_slice::<Sample, u8>(&samples)
+    );
+
+    // Truncated, too long or foreign files are rejected
+    assert!(decode(&bytes[..bytes.len() - 1]).is_none());
+    assert!(decode(&[bytes.as_slice(), &[0]].concat()).is_none());
+    assert!(decode(b"FTT0").is_none());
+}
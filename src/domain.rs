@@ -0,0 +1,128 @@
+use crate::fractal::FractalStep;
+use crate::util::*;
+
+/// Every formula made of these steps escapes when |c| is larger than this
+const ESCAPE_RADIUS: f64 = 2.0;
+
+/// Room around the escape radius, so the outside of the fractal is still visible
+const MARGIN: f64 = 1.0;
+
+/// Pixels closer together than this many units in the last place all look the same
+const MIN_ULPS_PER_PIXEL: f64 = 8.0;
+
+/// Pixels along the side of a tile
+const TILE_SIZE: f64 = 256.0;
+
+/// Where a fractal is worth looking at, and how deep it can be zoomed
+#[derive(Clone, Copy, Debug)]
+pub struct Domain {
+    /// The center of the view stays in here, and no tiles are built outside of it
+    pub bounds: Rect,
+
+    /// Smallest zoom level, the entire domain fits on the screen twice
+    pub min_zoom: f64,
+
+    /// Smallest distance between two pixels in world space, below this the most precise backend
+    /// runs out of precision and the tiles turn into blocks
+    pub min_pixel_size: f64,
+
+    /// Deepest tile level built by the less precise gpu backend, deeper tiles are built on the
+    /// cpu. This is where its pixels are an ulp apart, not `MIN_ULPS_PER_PIXEL`.
+    pub gpu_max_z: u8,
+}
+
+impl Domain {
+    /// The domain of a formula, for backends with `gpu_precision` and `cpu_precision` bits in
+    /// the mantissa
+    pub fn new(steps: &[FractalStep], gpu_precision: u32, cpu_precision: u32) -> Self {
+        let radius = escape_radius(steps) + MARGIN;
+        let bounds = Rect::min_max(V2::new(-radius, -radius), V2::new(radius, radius));
+
+        // The coordinates are largest at the edge of the bounds
+        let min_pixel_size =
+            |precision: u32| radius * 0.5_f64.powi(precision as i32 - 1) * MIN_ULPS_PER_PIXEL;
+
+        // The pixels of a tile at level z are 0.5^z / TILE_SIZE apart. The gpu keeps going until
+        // they are an ulp apart around |c| = 1, where most of the detail is. Further out they
+        // already merge into small blocks, but the cpu is many times slower, so it only takes
+        // over once the gpu runs out of precision everywhere.
+        let gpu_pixel_size = 0.5_f64.powi(gpu_precision as i32 - 1);
+        let gpu_max_z = -(gpu_pixel_size * TILE_SIZE).log2();
+
+        Domain {
+            bounds,
+            min_zoom: -(radius * 4.0).log2(),
+            min_pixel_size: min_pixel_size(cpu_precision),
+            gpu_max_z: gpu_max_z.floor().max(0.0) as u8,
+        }
+    }
+
+    /// Largest zoom level for a screen this many pixels wide
+    pub fn max_zoom(&self, width: f64) -> f64 {
+        -(self.min_pixel_size * width.max(1.0)).log2()
+    }
+
+    /// Keep a point inside the bounds
+    pub fn clamp(&self, p: V2) -> V2 {
+        let min = self.bounds.corner_min();
+        let max = self.bounds.corner_max();
+        V2::new(p.x.clamp(min.x, max.x), p.y.clamp(min.y, max.y))
+    }
+
+    /// Keep a point inside the tiles that overlap the bounds. When the bounds end on a tile
+    /// edge, a point on that edge would be in the next tile, which is entirely outside.
+    pub fn clamp_tiles(&self, p: V2) -> V2 {
+        let max = self.bounds.corner_max();
+        let p = self.clamp(p);
+        V2::new(p.x.min(max.x.next_down()), p.y.min(max.y.next_down()))
+    }
+}
+
+/// Outside a circle with this radius c escapes right away
+///
+/// With a single power d per iteration |z^d + c| > |z| when |z| >= |c| > 2^(1/(d-1)). Adding c
+/// more than once or using several powers is at most as large as the Mandelbrot set, which is
+/// within 2.
+fn escape_radius(steps: &[FractalStep]) -> f64 {
+    let degree = steps
+        .iter()
+        .map(|s| match s {
+            FractalStep::Square => 2,
+            FractalStep::Cube => 3,
+            _ => 1,
+        })
+        .product::<i32>();
+    let add_count = steps.iter().filter(|s| **s == FractalStep::AddC).count();
+
+    // Without a power c only moves the orbit, there is nothing to see
+    if degree == 1 {
+        return 0.0;
+    }
+
+    if add_count == 1 {
+        2.0_f64.powf(1.0 / (degree - 1) as f64)
+    } else {
+        ESCAPE_RADIUS
+    }
+}
+
+#[test]
+fn test_domain() {
+    let mandelbrot = [FractalStep::Square, FractalStep::AddC];
+    let d = Domain::new(&mandelbrot, f32::MANTISSA_DIGITS, f64::MANTISSA_DIGITS);
+    assert_eq!(d.bounds.corner_max(), V2::new(3.0, 3.0));
+    assert_eq!(d.gpu_max_z, 15);
+
+    // The edge of the bounds is in the last tile inside them
+    let edge = d.clamp_tiles(V2::new(5.0, -5.0));
+    for z in [0, 4, 20, 40] {
+        let p = crate::tilemap::TilePos::at(edge.x, edge.y, z);
+        assert_eq!(p.x, (3_i64 << z) - 1);
+        assert_eq!(p.y, -(3_i64 << z));
+    }
+
+    // A cubic escapes sooner
+    let cubic = [FractalStep::Cube, FractalStep::AddC];
+    let d = Domain::new(&cubic, f32::MANTISSA_DIGITS, f64::MANTISSA_DIGITS);
+    assert!((d.bounds.corner_max().x - 2.0_f64.sqrt() - MARGIN).abs() < 1e-12);
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use ::image::ColorType;
+
+use crate::builder::TileBuilder;
+use crate::color::Coloring;
+use crate::image::Image;
+use crate::tilemap::TilePos;
+use crate::util::*;
+use crate::viewport::Viewport;
+
+/// Exported images are written to the working directory, numbered so nothing is overwritten
+const EXPORT_PREFIX: &str = "fractal-toy-";
+
+/// Render the center of the screen from the tiles we already have, scaled to fit in `size`
+///
+/// Every pixel uses the most detailed tile that was built, parts without any tile are black.
+/// Supersampled pixels have the average colour of their samples, like on the screen.
+pub fn render(
+    viewport: &Viewport,
+    builder: &mut TileBuilder,
+    coloring: &Coloring,
+    size: V2<u32>,
+) -> Image {
+    let z_max = viewport
+        .get_pos_all(0)
+        .iter()
+        .map(|p| p.z)
+        .max()
+        .unwrap_or(0);
+    let size_f = size.map(|x| x as f64);
+    let screen = viewport.size_in_pixels;
+    let scale = (screen.x / size_f.x).min(screen.y / size_f.y);
+
+    let mut tiles: BTreeMap<TilePos, Option<Image>> = BTreeMap::new();
+    let mut data = vec![0_u8; (size.x * size.y * 4) as usize];
+    for (i, dst) in data.chunks_exact_mut(4).enumerate() {
+        let t = V2::new((i as u32 % size.x) as f64, (i as u32 / size.x) as f64);
+        let p = screen * 0.5 + (t + V2::new(0.5, 0.5) - size_f * 0.5) * scale;
+        let world = viewport.screen_to_world_exact(p);
+
+        // The most detailed tile at this point
+        let found = (0..=z_max).rev().find_map(|z| {
+            let pos = TilePos::at(world.x, world.y, z);
+            let img = tiles.entry(pos).or_insert_with(|| {
+                let tile = builder.tile(&pos)?;
+                Some(coloring.image(tile))
+            });
+            img.clone().map(|img| (pos, img))
+        });
+
+        dst[3] = 255;
+        if let Some((pos, img)) = found {
+            let square = pos.square();
+            let u = (world - square.corner_min()) / square.size().x;
+            let img_size = img.size();
+            let x = ((u.x * img_size.x as f64) as u32).min(img_size.x - 1);
+            let y = ((u.y * img_size.y as f64) as u32).min(img_size.y - 1);
+            let j = ((y * img_size.x + x) * 4) as usize;
+            dst.copy_from_slice(&img.data()[j..j + 4]);
+        }
+    }
+
+    Image::new(size, data)
+}
+
+/// Are all visible tiles built with all their samples, so the view can be exported
+pub fn is_ready(viewport: &Viewport, builder: &mut TileBuilder) -> bool {
+    !viewport.is_flying()
+        && (viewport.get_pos_all(0).iter())
+            .all(|p| builder.tile(p).is_some_and(|t| t.data.is_complete()))
+}
+
+/// Write the view to a new png in the working directory, at the resolution of the screen
+pub fn export(
+    viewport: &Viewport,
+    builder: &mut TileBuilder,
+    coloring: &Coloring,
+) -> Result<PathBuf, String> {
+    let size = viewport.size_in_pixels_i;
+    let img = render(viewport, builder, coloring, size);
+    let path = (1..)
+        .map(|i| PathBuf::from(format!("{}{}.png", EXPORT_PREFIX, i)))
+        .find(|path| !path.exists())
+        .unwrap();
+
+    ::image::save_buffer(&path, img.data(), size.x, size.y, ColorType::Rgba8)
+        .map_err(|e| e.to_string())?;
+    Ok(path)
+}
@@ -0,0 +1,300 @@
+use crate::asset_loader::AssetLoader;
+use crate::asset_loader::FontType;
+use crate::asset_loader::TextAlignment;
+use crate::fractal::FractalStep;
+use crate::image::Image;
+use crate::orbit;
+use crate::state::State;
+use crate::ui::Window;
+use crate::ui::UI;
+use crate::util::*;
+use crate::viewport::Location;
+use crate::viewport::Viewport;
+
+/// The box around the cursor starts this many pixels large, and doubles until a period is found
+const MIN_BOX_PIXELS: f64 = 4.0;
+
+/// After zooming to a minibrot, the view is this many times wider than its size estimate
+const ZOOM_MARGIN: f64 = 4.0;
+
+/// Misiurewicz points have no size, zooming to one goes this many levels deeper
+const MISIUREWICZ_ZOOM: f64 = 4.0;
+
+/// Highest preperiod and period of the Misiurewicz points we look for
+const MAX_MISIUREWICZ_PERIOD: u32 = 32;
+
+/// Number of guesses that are refined with Newton, most of them end up at the same few points
+const MISIUREWICZ_GUESSES: usize = 16;
+
+const MAX_RESULTS: usize = 5;
+
+const MARKER_SIZE: f64 = 8.0;
+
+const WINDOW_WIDTH: f64 = 400.0;
+const PADDING: f64 = 8.0;
+const LINE_HEIGHT: f64 = 28.0;
+const BUTTON_SIZE: V2 = V2::new(90.0, 30.0);
+const TEXT_SIZE: f32 = 22.0;
+
+/// Something worth zooming into
+#[derive(Clone, Copy, Debug)]
+pub enum Feature {
+    /// A minibrot, the center of its cardioid is the nucleus
+    Minibrot {
+        nucleus: V2,
+        period: u32,
+
+        /// Estimated size relative to the whole set, see `orbit::minibrot_size`
+        size: f64,
+    },
+
+    /// A point whose orbit becomes periodic after the preperiod, spirals and dendrites are
+    /// centered on them
+    Misiurewicz { c: V2, preperiod: u32, period: u32 },
+}
+
+impl Feature {
+    pub fn point(&self) -> V2 {
+        match *self {
+            Feature::Minibrot { nucleus, .. } => nucleus,
+            Feature::Misiurewicz { c, .. } => c,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            Feature::Minibrot { period, .. } => format!("Minibrot of period {}", period),
+            Feature::Misiurewicz {
+                preperiod, period, ..
+            } => format!("Misiurewicz point M{},{}", preperiod, period),
+        }
+    }
+
+    /// Where to look at the feature, a minibrot fills the screen
+    pub fn location(&self, viewport: &Viewport) -> Location {
+        let zoom = match *self {
+            Feature::Minibrot { size, .. } => -(size * ZOOM_MARGIN).log2(),
+            Feature::Misiurewicz { .. } => viewport.zoom + MISIUREWICZ_ZOOM,
+        };
+        let max_zoom = viewport.domain.max_zoom(viewport.size_in_pixels.x);
+        Location {
+            offset: self.point(),
+            zoom: zoom.min(max_zoom),
+            rotation: viewport.rotation,
+        }
+    }
+}
+
+/// What was clicked in the finder window
+pub enum FinderAction {
+    Zoom(Location),
+    Bookmark(String, Location),
+}
+
+/// Finds minibrots and Misiurewicz points near a point on the screen
+///
+/// For a minibrot the period is found with the box period method, starting with a small box
+/// around the point. Then Newton's method finds the nucleus with that period. For Misiurewicz
+/// points the preperiod and period are guessed from where the orbit almost repeats, and refined
+/// with Newton.
+pub struct Finder {
+    /// A search was done, the results are shown until they are closed
+    visible: bool,
+
+    /// What the last search was for, to say what was not found
+    searched: &'static str,
+
+    /// The nearest first
+    results: Vec<Feature>,
+
+    marker: Image,
+}
+
+impl Finder {
+    pub fn new() -> Self {
+        Finder {
+            visible: false,
+            searched: "",
+            results: Vec::new(),
+            marker: Image::new(V2::new(4, 4), [80, 200, 255, 255].repeat(16)),
+        }
+    }
+
+    /// Forget the results, they are no longer valid after the formula changed
+    pub fn clear(&mut self) {
+        self.visible = false;
+        self.results.clear();
+    }
+
+    /// Search the nearest minibrot from this position on the screen, with periods up to
+    /// `iter_count`
+    pub fn search(
+        &mut self,
+        steps: &[FractalStep],
+        viewport: &Viewport,
+        iter_count: u32,
+        p: V2<i32>,
+    ) {
+        let c = viewport.screen_to_world(p);
+        let pixel_size = viewport.pixel_size();
+
+        self.visible = true;
+        self.searched = "minibrot";
+        self.results.clear();
+
+        let mut radius = MIN_BOX_PIXELS * pixel_size;
+        while radius <= max_radius(viewport) {
+            let period = orbit::box_period(steps, c, radius, iter_count);
+            let nucleus = period.and_then(|period| {
+                let nucleus = orbit::find_nucleus(steps, c, period)?;
+                Some((nucleus, period))
+            });
+
+            // Newton can end up at another nucleus with the same period, far away
+            if let Some((nucleus, period)) = nucleus {
+                if (nucleus - c).magnitude() <= radius * 2.0 {
+                    let size = orbit::minibrot_size(steps, nucleus, period);
+                    self.results.push(Feature::Minibrot {
+                        nucleus,
+                        period,
+                        size,
+                    });
+                    return;
+                }
+            }
+
+            radius *= 2.0;
+        }
+    }
+
+    /// Search the Misiurewicz points near this position on the screen
+    pub fn search_misiurewicz(&mut self, steps: &[FractalStep], viewport: &Viewport, p: V2<i32>) {
+        let c = viewport.screen_to_world(p);
+        let pixel_size = viewport.pixel_size();
+
+        self.visible = true;
+        self.searched = "Misiurewicz point";
+        self.results.clear();
+
+        let guesses = orbit::misiurewicz_guesses(steps, c, MAX_MISIUREWICZ_PERIOD);
+        for (preperiod, period) in guesses.into_iter().take(MISIUREWICZ_GUESSES) {
+            let found = match orbit::find_misiurewicz(steps, c, preperiod, period) {
+                Some(found) if (found - c).magnitude() <= max_radius(viewport) => found,
+                _ => continue,
+            };
+
+            // Newton also converges to points with a lower preperiod or period, and to nuclei
+            let (preperiod, period) =
+                match orbit::misiurewicz_type(steps, found, MAX_MISIUREWICZ_PERIOD) {
+                    Some(t) => t,
+                    None => continue,
+                };
+
+            if self
+                .results
+                .iter()
+                .any(|f| (f.point() - found).magnitude() < pixel_size)
+            {
+                continue;
+            }
+
+            self.results.push(Feature::Misiurewicz {
+                c: found,
+                preperiod,
+                period,
+            });
+        }
+
+        let distance = |f: &Feature| (f.point() - c).magnitude();
+        self.results
+            .sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        self.results.truncate(MAX_RESULTS);
+    }
+
+    /// Show the results, returns what was clicked
+    pub fn update(&mut self, state: &mut State, viewport: &Viewport) -> Option<FinderAction> {
+        if !self.visible {
+            return None;
+        }
+
+        // Mark the results, minibrots with a square as large as their size estimate
+        for f in self.results.iter() {
+            let p = viewport.world_to_screen_exact(f.point());
+            let size = V2::new(MARKER_SIZE, MARKER_SIZE);
+            state.gpu.blit(&Rect::center_size(p, size), &self.marker);
+
+            if let Feature::Minibrot { size, .. } = *f {
+                let size = size / viewport.pixel_size();
+                if size > MARKER_SIZE {
+                    let rect = Rect::center_size(p, V2::new(size, size) * 2.0);
+                    UI::outline(&mut state.gpu, &mut state.asset, &rect);
+                }
+            }
+        }
+
+        let mut window = state.ui.begin_window("finder", V2::new(480.0, 20.0));
+        let mut action = None;
+        let mut pos = V2::new(PADDING, PADDING);
+
+        if self.results.is_empty() {
+            let line = format!("no {} found here", self.searched);
+            text_line(&mut window, &mut state.asset, pos, &line);
+            pos.y += LINE_HEIGHT;
+        }
+
+        // One row per result, with its details and the buttons below them
+        for f in self.results.iter() {
+            let mut lines = vec![f.name()];
+            if let Feature::Minibrot { size, .. } = f {
+                lines.push(format!("size {:.3e}", size));
+            }
+            lines.push(format_complex(f.point(), viewport.pixel_size()));
+            for line in lines {
+                text_line(&mut window, &mut state.asset, pos, &line);
+                pos.y += LINE_HEIGHT;
+            }
+
+            let rect = Rect::corner_size(pos, BUTTON_SIZE);
+            if window.button(&mut state.ui, &mut state.asset, rect, "zoom", TEXT_SIZE) {
+                action = Some(FinderAction::Zoom(f.location(viewport)));
+            }
+            let rect = Rect::corner_size(pos + V2::new(BUTTON_SIZE.x + PADDING, 0.0), BUTTON_SIZE);
+            if window.button(&mut state.ui, &mut state.asset, rect, "bookmark", TEXT_SIZE) {
+                action = Some(FinderAction::Bookmark(f.name(), f.location(viewport)));
+            }
+            pos.y += BUTTON_SIZE.y + PADDING * 2.0;
+        }
+
+        pos.x = WINDOW_WIDTH - PADDING - BUTTON_SIZE.x;
+        let rect = Rect::corner_size(pos, BUTTON_SIZE);
+        let close = window.button(&mut state.ui, &mut state.asset, rect, "close", TEXT_SIZE);
+        window.reserve(Rect::min_max(
+            V2::zero(),
+            V2::new(WINDOW_WIDTH, pos.y + BUTTON_SIZE.y + PADDING),
+        ));
+        state.ui.end_window(window);
+
+        if close {
+            self.visible = false;
+        }
+
+        action
+    }
+}
+
+fn text_line(window: &mut Window, asset: &mut AssetLoader, pos: V2, line: &str) {
+    window.text(
+        asset,
+        FontType::Normal,
+        (pos + V2::new(0.0, LINE_HEIGHT * 0.5)).map(|x| x as _),
+        V2::new(TextAlignment::Left, TextAlignment::Center),
+        TEXT_SIZE,
+        line,
+    );
+}
+
+/// Results further away than this from the cursor are not near it anymore
+fn max_radius(viewport: &Viewport) -> f64 {
+    let screen = viewport.size_in_pixels;
+    screen.x.min(screen.y) * 0.5 * viewport.pixel_size()
+}
@@ -0,0 +1,168 @@
+use crate::util::*;
+use crate::viewport::Location;
+
+/// Curvature of the path, sqrt(2) is what most people find the most natural
+const RHO: f64 = std::f64::consts::SQRT_2;
+
+/// Speed along the path, in screen widths per second
+const SPEED: f64 = 1.5;
+
+/// Even a tiny jump is animated, so it is clear what happened
+const MIN_DURATION: f64 = 0.5;
+
+/// An animated camera path between two locations.
+///
+/// Zooms out, pans and zooms in again, such that the perceived speed is constant.
+/// See "Smooth and efficient zooming and panning" by van Wijk and Nuij.
+#[derive(Clone, Debug)]
+pub struct Flight {
+    from: Location,
+    to: Location,
+
+    /// Widths of the view at both ends, in world units
+    w0: f64,
+    w1: f64,
+
+    /// Distance between both centers
+    d: f64,
+
+    /// Parameters of the path at both ends, only used when `d` is not zero
+    r0: f64,
+    r1: f64,
+
+    /// Length of the path, in screen widths
+    length: f64,
+}
+
+impl Flight {
+    pub fn new(from: Location, to: Location) -> Self {
+        let w0 = 0.5_f64.powf(from.zoom);
+        let w1 = 0.5_f64.powf(to.zoom);
+        let d = (to.offset - from.offset).magnitude();
+
+        // Without any movement this is just a zoom, the path below would divide by zero
+        let rho2 = RHO * RHO;
+        let (r0, r1) = if d < w0.min(w1) * 1e-6 {
+            (0.0, (w1 / w0).ln().abs())
+        } else {
+            // ln(sqrt(b^2 + 1) - b) = -asinh(b), but without the cancellation
+            let b0 = (w1 * w1 - w0 * w0 + rho2 * rho2 * d * d) / (2.0 * w0 * rho2 * d);
+            let b1 = (w1 * w1 - w0 * w0 - rho2 * rho2 * d * d) / (2.0 * w1 * rho2 * d);
+            (-b0.asinh(), -b1.asinh())
+        };
+
+        Flight {
+            from,
+            to,
+            w0,
+            w1,
+            d,
+            r0,
+            r1,
+            length: (r1 - r0) / RHO,
+        }
+    }
+
+    pub fn target(&self) -> Location {
+        self.to
+    }
+
+    /// Time in seconds to fly the whole path
+    pub fn duration(&self) -> f64 {
+        (self.length / SPEED).max(MIN_DURATION)
+    }
+
+    /// The location after `time` seconds, the path starts and stops smoothly
+    pub fn at(&self, time: f64) -> Location {
+        let t = (time / self.duration()).clamp(0.0, 1.0);
+        if t <= 0.0 {
+            return self.from;
+        }
+        if t >= 1.0 {
+            return self.to;
+        }
+
+        let t = t * t * (3.0 - 2.0 * t);
+        let s = t * self.length;
+
+        let (offset, w) = if self.d < self.w0.min(self.w1) * 1e-6 {
+            let offset = self.from.offset + (self.to.offset - self.from.offset) * t;
+            (offset, self.w0 * (self.w1 / self.w0).powf(t))
+        } else {
+            // Each half is computed from its own end, otherwise the deep end of the path would
+            // not have enough precision. cosh(a) tanh(x) - sinh(a) = sinh(x - a) / cosh(x)
+            let dir = (self.to.offset - self.from.offset) / self.d;
+            if s <= self.length * 0.5 {
+                let r = RHO * s + self.r0;
+                let u = self.w0 / (RHO * RHO) * (RHO * s).sinh() / r.cosh();
+                let w = self.w0 * self.r0.cosh() / r.cosh();
+                (self.from.offset + dir * u, w)
+            } else {
+                let s = self.length - s;
+                let r = RHO * s - self.r1;
+                let u = self.w1 / (RHO * RHO) * (RHO * s).sinh() / r.cosh();
+                let w = self.w1 * self.r1.cosh() / r.cosh();
+                (self.to.offset - dir * u, w)
+            }
+        };
+
+        // The shortest way around
+        let pi = std::f64::consts::PI;
+        let turn = (self.to.rotation - self.from.rotation + pi).rem_euclid(2.0 * pi) - pi;
+
+        Location {
+            offset,
+            zoom: -w.log2(),
+            rotation: self.from.rotation + turn * t,
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_flights() -> Vec<Flight> {
+    let at = |x, y, zoom, rotation| Location {
+        offset: V2::new(x, y),
+        zoom,
+        rotation,
+    };
+
+    vec![
+        Flight::new(at(-0.5, 0.0, 1.0, 0.0), at(-1.75, 0.01, 12.0, 1.0)),
+        Flight::new(at(-1.75, 0.01, 30.0, 0.0), at(0.25, 0.0, 2.0, -3.0)),
+        Flight::new(at(0.3, 0.5, 4.0, 0.0), at(0.3, 0.5, 20.0, 0.0)),
+        Flight::new(at(0.3, 0.5, 4.0, 0.0), at(0.31, 0.5, 4.0, 0.0)),
+    ]
+}
+
+#[test]
+fn test_endpoints() {
+    for f in test_flights() {
+        assert_eq!(f.at(0.0), f.from);
+        assert_eq!(f.at(-1.0), f.from);
+        assert_eq!(f.at(f.duration()), f.to);
+        assert_eq!(f.at(f.duration() + 1.0), f.to);
+
+        // And close to them
+        let near = f.at(f.duration() * 1e-6);
+        assert!((near.offset - f.from.offset).magnitude() < 1e-6 * 0.5_f64.powf(f.from.zoom));
+        assert!((near.zoom - f.from.zoom).abs() < 1e-6);
+        let near = f.at(f.duration() * (1.0 - 1e-6));
+        assert!((near.offset - f.to.offset).magnitude() < 1e-6 * 0.5_f64.powf(f.to.zoom));
+        assert!((near.zoom - f.to.zoom).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_continuous() {
+    for f in test_flights() {
+        // Both halves are computed from their own end, so they have to meet in the middle
+        let mid = f.duration() * 0.5;
+        let eps = f.duration() * 1e-9;
+        let a = f.at(mid - eps);
+        let b = f.at(mid + eps);
+        let width = 0.5_f64.powf(a.zoom);
+        assert!((a.offset - b.offset).magnitude() < width * 1e-6);
+        assert!((a.zoom - b.zoom).abs() < 1e-6);
+        assert!((a.rotation - b.rotation).abs() < 1e-6);
+    }
+}
@@ -1,15 +1,41 @@
 use cgmath::vec2;
 use cgmath::InnerSpace;
+use serde::Deserialize;
+use serde::Serialize;
 use winit::event::VirtualKeyCode;
 use winit::window::Window;
 
 use crate::asset_loader::FontType;
 use crate::asset_loader::TextAlignment;
+use crate::bookmarks::Bookmarks;
+use crate::builder::Tile;
 use crate::builder::TileBuilder;
+use crate::builder::DEFAULT_ITER_COUNT;
+use crate::camera_path::CameraPath;
+use crate::camera_path::Keyframe;
+use crate::color::ColorMode;
+use crate::color::Coloring;
+use crate::color::InteriorMode;
+use crate::color::Light;
 use crate::debug::Debug;
+use crate::disk_cache::DEFAULT_DISK_CACHE_LIMIT;
+use crate::export;
+use crate::finder::Finder;
+use crate::finder::FinderAction;
+use crate::history::History;
+use crate::history::HistoryEntry;
+use crate::inspector::Inspector;
+use crate::minimap::Minimap;
+use crate::sampling::Sampling;
+use crate::settings::Settings;
+use crate::settings::WorkerConfig;
 use crate::state::State;
+use crate::tile_cache::DEFAULT_CACHE_BUDGET;
+use crate::tilemap::TilePos;
+use crate::ui::UI;
 use crate::update_loop::Input;
 use crate::util::*;
+use crate::viewport::Location;
 use crate::viewport::Viewport;
 use crate::viewport::ViewportInput;
 
@@ -55,7 +81,7 @@ static STEP_VALUES: &[FractalStep] = &[
     FractalStep::Conj,
 ];
 
-#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum FractalStep {
     /// z = z^2
     Square,
@@ -89,30 +115,367 @@ impl FractalStep {
     }
 }
 
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum TrapShape {
+    Point,
+    Line,
+    Cross,
+    Circle,
+}
+
+impl TrapShape {
+    pub fn next(self) -> Self {
+        match self {
+            TrapShape::Point => TrapShape::Line,
+            TrapShape::Line => TrapShape::Cross,
+            TrapShape::Cross => TrapShape::Circle,
+            TrapShape::Circle => TrapShape::Point,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            TrapShape::Point => "point",
+            TrapShape::Line => "line",
+            TrapShape::Cross => "cross",
+            TrapShape::Circle => "circle",
+        }
+    }
+}
+
+/// While iterating we remember how close z came to this shape
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct OrbitTrap {
+    pub shape: TrapShape,
+    pub center: V2,
+
+    /// Rotation of the line and cross in radians
+    pub angle: f64,
+
+    /// Only used by the circle
+    pub radius: f64,
+}
+
+impl OrbitTrap {
+    pub fn new() -> Self {
+        OrbitTrap {
+            shape: TrapShape::Point,
+            center: V2::zero(),
+            angle: 0.0,
+            radius: 1.0,
+        }
+    }
+
+    pub fn distance(&self, z: V2) -> f64 {
+        let p = z - self.center;
+
+        // rotate into the frame of the trap
+        let (s, c) = self.angle.sin_cos();
+        let p = V2::new(p.x * c + p.y * s, p.y * c - p.x * s);
+
+        match self.shape {
+            TrapShape::Point => p.magnitude(),
+            TrapShape::Line => p.y.abs(),
+            TrapShape::Cross => p.x.abs().min(p.y.abs()),
+            TrapShape::Circle => (p.magnitude() - self.radius).abs(),
+        }
+    }
+}
+
+/// Everything that is stored when saving the state
+#[derive(Serialize, Deserialize)]
+struct SaveState {
+    steps: Vec<FractalStep>,
+    trap: OrbitTrap,
+    offset: V2,
+    zoom: f64,
+    color_mode: ColorMode,
+    light: Light,
+
+    #[serde(default)]
+    interior: InteriorMode,
+
+    #[serde(default)]
+    sampling: Sampling,
+
+    #[serde(default)]
+    rotation: f64,
+
+    #[serde(default)]
+    palette_offset: f64,
+
+    #[serde(default)]
+    path: CameraPath,
+
+    #[serde(default = "default_iter_count")]
+    iter_count: u32,
+}
+
+fn default_iter_count() -> u32 {
+    DEFAULT_ITER_COUNT
+}
+
+/// Tiles of the previous formula are dimmed, to show that they are outdated
+const PREVIOUS_TILE_COLOR: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+
+/// The cache budget can't be lowered below this, otherwise the visible tiles do not fit
+const MIN_CACHE_BUDGET: usize = 64 * 1024 * 1024;
+
+/// Limits of the iteration count, the histogram of a tile does not go beyond the maximum
+const MIN_ITER_COUNT: u32 = 64;
+const MAX_ITER_COUNT: u32 = 1 << 16;
+
+/// Rectangles smaller than this many pixels are not zoomed into, that was probably a click
+const MIN_BOX_ZOOM: i32 = 8;
+
+/// Palette cycles per second while shifting it
+const PALETTE_SPEED: f64 = 0.25;
+
+/// Time in seconds for a new tile to fade in over its placeholder
+const FADE_TIME: f32 = 0.25;
+
+const SAVE_DIR: &str = ".fractal-toy";
+const SAVE_FILE: &str = ".fractal-toy/state.ron";
+
 pub struct Fractal {
     // actual state that is relevant
     builder: TileBuilder,
+
+    /// The builder of the previous formula, its tiles are shown until the new ones are built
+    previous_builder: Option<TileBuilder>,
+
+    /// The current builder has at least one visible tile
+    has_visible_tiles: bool,
+
+    coloring: Coloring,
     viewport: Viewport,
     steps: Vec<FractalStep>,
+    trap: OrbitTrap,
+    sampling: Sampling,
+
+    /// Maximum number of iterations
+    iter_count: u32,
+
+    /// Memory budget of the tile cache in bytes
+    ///
+    /// After the formula changed, the old tiles that are still shown come on top of this. Those
+    /// are dropped as soon as they are no longer drawn.
+    cache_budget: usize,
+
+    /// The trap was edited, but the builder was not yet recreated
+    trap_changed: bool,
+
+    settings: Settings,
+
+    /// The worker configuration changed, the builder has to be recreated
+    workers_changed: bool,
+
+    /// Keyframes for animations
+    path: CameraPath,
+
+    /// Time in the path while previewing it
+    playback: Option<f64>,
+
+    /// Where we are in the path, keyframes are inserted and removed here
+    path_time: f64,
+
+    /// The view is exported once all its tiles are built, with all their samples
+    export_pending: bool,
+
+    /// Screen position where the rectangle to zoom into started
+    box_zoom: Option<V2<i32>>,
+
+    /// The left button was used to zoom, so it does not drag
+    click_zoomed: bool,
+
+    minimap: Minimap,
+
+    /// Undo and redo for the view and the formula
+    history: History,
+
+    bookmarks: Bookmarks,
+
+    /// Shows the orbit of the point under the cursor
+    inspector: Inspector,
+
+    /// Finds minibrots near the cursor
+    finder: Finder,
 }
 
 impl Fractal {
-    pub fn init(state: &mut State) -> Self {
+    pub fn init(state: &mut State, settings: Settings) -> Self {
         let steps = MANDELBROT.to_vec();
-        let builder = TileBuilder::new(state.gpu.device(), &mut state.asset, &steps);
+        let trap = OrbitTrap::new();
+        let sampling = Sampling::Single;
+        let builder = TileBuilder::new(
+            state.gpu.device(),
+            &mut state.asset,
+            &steps,
+            &trap,
+            sampling,
+            DEFAULT_ITER_COUNT,
+            DEFAULT_CACHE_BUDGET,
+            settings.disk_cache.then_some(DEFAULT_DISK_CACHE_LIMIT),
+            &settings.workers,
+        );
         Fractal {
             builder,
-            viewport: Viewport::new(),
+            previous_builder: None,
+            has_visible_tiles: false,
+            coloring: Coloring::new(),
+            viewport: Viewport::new(TileBuilder::domain(&steps)),
             steps,
+            trap,
+            sampling,
+            iter_count: DEFAULT_ITER_COUNT,
+            cache_budget: DEFAULT_CACHE_BUDGET,
+            trap_changed: false,
+            settings,
+            workers_changed: false,
+            path: CameraPath::default(),
+            path_time: 0.0,
+            playback: None,
+            export_pending: false,
+            box_zoom: None,
+            click_zoomed: false,
+            minimap: Minimap::new(),
+            history: History::new(),
+            bookmarks: Bookmarks::load(),
+            inspector: Inspector::new(),
+            finder: Finder::new(),
         }
     }
 
+    fn history_entry(&self) -> HistoryEntry {
+        HistoryEntry {
+            location: self.viewport.target(),
+            steps: self.steps.clone(),
+        }
+    }
+
+    /// Fly to a view, returns true if the formula changed
+    fn go_to(&mut self, entry: HistoryEntry) -> bool {
+        self.playback = None;
+        self.viewport.fly_to(entry.location);
+        if entry.steps == self.steps {
+            return false;
+        }
+        self.steps = entry.steps;
+        true
+    }
+
+    /// Use the palette and the iteration count of a keyframe, returns true if the builder has to
+    /// be recreated
+    fn show_keyframe(&mut self, key: &Keyframe) -> bool {
+        self.coloring.set_palette_offset(key.palette_offset);
+        let changed = key.iter_count != self.iter_count;
+        self.iter_count = key.iter_count;
+        changed
+    }
+
+    /// The keyboard is used to type text, it should not control anything else
+    pub fn is_typing(&self) -> bool {
+        self.bookmarks.is_typing()
+    }
+
+    /// Change the number of workers and their priority, this rebuilds all tiles
+    pub fn set_worker_config(&mut self, config: WorkerConfig) {
+        if config != self.settings.workers {
+            self.settings.workers = config;
+            self.workers_changed = true;
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let save = SaveState {
+            steps: self.steps.clone(),
+            trap: self.trap,
+            offset: self.viewport.offset,
+            zoom: self.viewport.zoom,
+            color_mode: self.coloring.mode,
+            light: self.coloring.light,
+            interior: self.coloring.interior,
+            sampling: self.sampling,
+            iter_count: self.iter_count,
+            rotation: self.viewport.rotation,
+            palette_offset: self.coloring.palette_offset,
+            path: self.path.clone(),
+        };
+
+        let data = ron::ser::to_string_pretty(&save, ron::ser::PrettyConfig::default()).unwrap();
+        std::fs::create_dir_all(SAVE_DIR)?;
+        std::fs::write(SAVE_FILE, data)
+    }
+
+    /// Load the saved state, the builder still has to be recreated after this
+    fn load(&mut self) -> Result<(), String> {
+        let data = std::fs::read_to_string(SAVE_FILE).map_err(|e| e.to_string())?;
+        let save: SaveState = ron::from_str(&data).map_err(|e| e.to_string())?;
+        self.steps = save.steps;
+        self.trap = save.trap;
+
+        // Animate, so it is clear where the saved location is relative to the current one
+        self.viewport.fly_to(Location {
+            offset: save.offset,
+            zoom: save.zoom,
+            rotation: save.rotation,
+        });
+
+        self.coloring.set_mode(save.color_mode);
+        self.coloring.set_light(save.light);
+        self.coloring.set_interior(save.interior);
+        self.coloring.set_palette_offset(save.palette_offset);
+        self.sampling = save.sampling;
+        self.iter_count = save.iter_count;
+        self.path = save.path;
+        self.playback = None;
+        Ok(())
+    }
+
     /// always called at regular intervals
     pub fn update(&mut self, state: &mut State, window: &Window, input: &Input) {
         let mut recreate_builder = false;
 
         Debug::push("fractal.update()");
 
+        // First, so the window gets the clicks before anything below it
+        Debug::push("bookmarks.update()");
+        let bookmark = self.bookmarks.update(
+            state,
+            input,
+            &self.viewport,
+            &mut self.builder,
+            &self.coloring,
+            &self.steps,
+        );
+        if let Some(entry) = bookmark {
+            self.history.record(self.history_entry());
+            recreate_builder |= self.go_to(entry);
+        }
+        Debug::pop();
+
+        Debug::push("finder.update()");
+        match self.finder.update(state, &self.viewport) {
+            Some(FinderAction::Zoom(location)) => {
+                self.history.record(self.history_entry());
+                self.viewport.fly_to(location);
+            }
+            Some(FinderAction::Bookmark(name, location)) => {
+                self.bookmarks.add_location(name, location, &self.steps);
+            }
+            None => (),
+        }
+        Debug::pop();
+
+        // Typing a name should not also move the view
+        let without_keys;
+        let input = if self.is_typing() {
+            without_keys = input.without_keys();
+            &without_keys
+        } else {
+            input
+        };
+
         {
             let mapped = map_input(input);
             let mut viewport_input = ViewportInput {
@@ -122,73 +485,252 @@ impl Fractal {
                 zoom_center: mapped.zoom,
                 drag: None,
                 scroll_at: (input.mouse, 0.0),
+                rotate: mapped.rotate,
+                rotate_drag: None,
             };
 
+            // A click that zooms should not also drag, until the button is released
+            if !input.mouse_down {
+                self.click_zoomed = false;
+            }
+
             // handle input for the viewport, if the user didn't click the ui
             if !state.ui.has_input() {
-                if input.mouse_down {
+                if input.mouse_double_click {
+                    let location = self.viewport.zoom_location(input.mouse, 1.0);
+                    self.viewport.fly_to(location);
+                    self.click_zoomed = true;
+                } else if input.mouse_click && input.ctrl() {
+                    let location = self.viewport.zoom_location(input.mouse, -1.0);
+                    self.viewport.fly_to(location);
+                    self.click_zoomed = true;
+                }
+
+                if input.mouse_down && !self.click_zoomed {
                     viewport_input.drag = Some(input.mouse);
                 }
+
+                if input.mouse_right_down && self.box_zoom.is_none() {
+                    if input.shift() {
+                        viewport_input.rotate_drag = Some(input.mouse);
+                    } else {
+                        self.box_zoom = Some(input.mouse);
+                    }
+                }
                 viewport_input.scroll_at.1 = input.mouse_scroll as f64;
             }
 
+            // Zoom into the rectangle when the button is released
+            if let (Some(start), false) = (self.box_zoom, input.mouse_right_down) {
+                self.box_zoom = None;
+                let d = input.mouse - start;
+                if d.x.abs() > MIN_BOX_ZOOM && d.y.abs() > MIN_BOX_ZOOM {
+                    let location = self.viewport.fit_location(start, input.mouse);
+                    self.viewport.fly_to(location);
+                }
+            }
+
             // resize viewport
             self.viewport.update(&viewport_input);
         }
 
+        // Preview the camera path, this overrides the user input
+        if let Some(time) = &mut self.playback {
+            *time += input.dt as f64;
+            self.path_time = *time;
+            if *time >= self.path.duration() {
+                self.playback = None;
+            }
+
+            if let Some(key) = self.path.at(self.path_time) {
+                self.viewport.set_location(key.location);
+                recreate_builder |= self.show_keyframe(&key);
+            }
+        }
+
         // queue which tiles should be built, we include a 1 tile border here
-        Debug::push("builder.tile() [build]");
+        Debug::push("builder.request()");
         for p in self.viewport.get_pos_all(1) {
-            self.builder.tile(&p);
+            let priority = self.viewport.tile_priority(&p);
+            self.builder.request(&p, priority);
+        }
+        Debug::pop();
+
+        recreate_builder |= self.settings_keys(input);
+        recreate_builder |= self.sampling_keys(input);
+        recreate_builder |= self.path_keys(input);
+        recreate_builder |= self.history_keys(input);
+        self.coloring_keys(input);
+        self.view_keys(input);
+
+        let visible = self.viewport.get_pos_all(0);
+
+        // Only the most detailed level is drawn, and used for coloring
+        let z_max = visible.iter().map(|p| p.z).max().unwrap_or(0);
+
+        // The colors can depend on all visible tiles
+        Debug::push("coloring.histogram()");
+        {
+            let mut complete = true;
+            self.coloring.begin();
+            for p in visible.iter().filter(|p| p.z == z_max) {
+                match self.builder.tile(p) {
+                    Some(tile) => {
+                        complete &= tile.data.is_complete();
+                        self.coloring.add(&tile.data);
+                    }
+                    None => complete = false,
+                }
+            }
+            self.coloring.end(complete);
         }
         Debug::pop();
 
         // draw tiles, without a border, so just those visible
         Debug::push("builder.tile() [draw]");
-        for p in self.viewport.get_pos_all(0) {
-            // if we don't have a tile don't draw it yet
-            if let Some(img) = self.builder.tile(&p) {
-                state.gpu.tile(&self.viewport, &p, img);
+        let mut all_visible = true;
+        self.has_visible_tiles = false;
+        for p in visible.iter().filter(|p| p.z == z_max) {
+            let tile = self.builder.tile(p).map(|tile| {
+                let fade = tile.received.elapsed().as_secs_f32() / FADE_TIME;
+                (self.coloring.image(tile), fade.min(1.0))
+            });
+
+            self.has_visible_tiles |= tile.is_some();
+
+            // Until the tile is fully visible, draw a placeholder below it.
+            // That is the tile of the previous formula, or else the closest ancestor we do have.
+            if !matches!(tile, Some((_, fade)) if fade >= 1.0) {
+                all_visible = false;
+
+                let previous = (self.previous_builder.as_mut())
+                    .and_then(|b| closest_tile(b, Some(*p)))
+                    .map(|(q, t)| (q, self.coloring.image(t), PREVIOUS_TILE_COLOR));
+
+                let placeholder = previous.or_else(|| {
+                    closest_tile(&mut self.builder, p.parent())
+                        .map(|(q, t)| (q, self.coloring.image(t), [1.0; 4]))
+                });
+
+                if let Some((q, img, color)) = placeholder {
+                    state.gpu.tile(&self.viewport, p, &q, &img, color);
+                }
+            }
+
+            if let Some((img, fade)) = tile {
+                let color = [1.0, 1.0, 1.0, fade];
+                state.gpu.tile(&self.viewport, p, p, &img, color);
             }
         }
         Debug::pop();
 
+        // The rectangle that will be zoomed into
+        if let Some(start) = self.box_zoom {
+            let a = start.map(|x| x as f64);
+            let b = input.mouse.map(|x| x as f64);
+            let min = V2::new(a.x.min(b.x), a.y.min(b.y));
+            let max = V2::new(a.x.max(b.x), a.y.max(b.y));
+            UI::outline(&mut state.gpu, &mut state.asset, &Rect::min_max(min, max));
+        }
+
+        if self.export_pending && export::is_ready(&self.viewport, &mut self.builder) {
+            self.export_pending = false;
+            match export::export(&self.viewport, &mut self.builder, &self.coloring) {
+                Ok(path) => println!("Exported the view to {}", path.display()),
+                Err(e) => eprintln!("Could not export the view: {}", e),
+            }
+        }
+
+        Debug::push("minimap.update()");
+        self.minimap.update(
+            state,
+            &mut self.builder,
+            &self.coloring,
+            &mut self.viewport,
+            input,
+        );
+        Debug::pop();
+
+        Debug::push("inspector.update()");
+        let (viewport, steps) = (&self.viewport, &self.steps);
+        let inspector = &mut self.inspector;
+        inspector.update(state, viewport, steps, self.iter_count, input);
+        Debug::pop();
+
+        // The new formula has replaced everything, so the old tiles are no longer needed
+        if all_visible {
+            self.previous_builder = None;
+        }
+
         // random information text
         state.debug.print(&Self::distance(self.viewport.scale));
+        let sampling = format!("sampling {}", self.sampling.name());
+        state.debug.print(&sampling);
+        let iterations = format!("iterations {}", self.iter_count);
+        state.debug.print(&iterations);
+        let queued = format!("queued {}", self.builder.queued());
+        state.debug.print(&queued);
+
+        let stats = self.builder.stats();
+        let mib = 1024 * 1024;
+        let cache = format!(
+            "cache {} tiles, {}/{} MiB\nhits {} misses {} evicted {}",
+            stats.tiles,
+            stats.bytes / mib,
+            self.cache_budget / mib,
+            stats.hits,
+            stats.misses,
+            stats.evictions,
+        );
+        state.debug.print(&cache);
+
+        let disk_cache = self.settings.disk_cache;
+        let disk_cache = format!("disk cache {}", if disk_cache { "on" } else { "off" });
+        state.debug.print(&disk_cache);
+
+        let mut path = format!(
+            "path {} keyframes, {:.1} s",
+            self.path.keyframes().len(),
+            self.path.duration()
+        );
+        match self.playback {
+            Some(time) => path += &format!(", playing {:.1} s", time),
+            None => path += &format!(", at {:.1} s", self.path_time),
+        }
+        state.debug.print(&path);
+
+        let history = format!(
+            "history undo {}, redo {}",
+            if self.history.can_undo() { "yes" } else { "no" },
+            if self.history.can_redo() { "yes" } else { "no" },
+        );
+        state.debug.print(&history);
+
+        let mode = if self.settings.workers.background {
+            "background"
+        } else {
+            "normal"
+        };
+        state.debug.print(&format!("workers ({} priority)", mode));
+        for w in self.builder.worker_metrics() {
+            let metrics = format!(
+                "  {:12} {:6} tiles {:6.1}/s {:3.0}% busy",
+                w.name,
+                w.tiles,
+                w.tiles_per_second,
+                w.busy * 100.0
+            );
+            state.debug.print(&metrics);
+        }
+
+        for e in self.builder.errors() {
+            state.debug.print(e);
+        }
 
         // The user interface buttons on the bottom
         {
             Debug::push("ui.buttons()");
 
-            fn do_button(state: &mut State, s: FractalStep, rect: Rect) -> bool {
-                let region = state.ui.region(&rect);
-                let image_back = state.asset.image("button_back.png");
-
-                state.gpu.blit(&rect, &image_back);
-                state.asset.text(
-                    FontType::Normal,
-                    rect.center().map(|x| x as _),
-                    V2 {
-                        x: TextAlignment::Center,
-                        y: TextAlignment::Center,
-                    },
-                    42.,
-                    &mut state.gpu,
-                    s.step_txt(),
-                );
-
-                let image_front = state.asset.image(if region.down {
-                    "button_front_down.png"
-                } else if region.hover {
-                    "button_front_hot.png"
-                } else {
-                    "button_front_norm.png"
-                });
-
-                state.gpu.blit(&rect, &image_front);
-                region.click
-            }
-
             // self.ui.text(&mut self.asset, &self.debug.draw());
 
             // Pick modules from these
@@ -196,7 +738,8 @@ impl Fractal {
             let mut pos = vec2(size.x * 0.5, self.viewport.size_in_pixels.y - size.y * 1.5);
             for s in STEP_VALUES.iter().copied() {
                 let rect = Rect::center_size(pos, size * 0.9);
-                if do_button(state, s, rect) {
+                if do_button(state, s.step_txt(), 42., rect) {
+                    self.history.record(self.history_entry());
                     self.steps.push(s);
                     recreate_builder = true;
                 }
@@ -209,13 +752,17 @@ impl Fractal {
             let mut remove = Vec::new();
             for (i, s) in self.steps.iter().copied().enumerate() {
                 let rect = Rect::center_size(pos, size * 0.9);
-                if do_button(state, s, rect) {
+                if do_button(state, s.step_txt(), 42., rect) {
                     remove.push(i);
                 }
 
                 pos.x += size.x;
             }
 
+            if !remove.is_empty() {
+                self.history.record(self.history_entry());
+            }
+
             for i in remove {
                 self.steps.remove(i);
                 recreate_builder = true;
@@ -224,6 +771,64 @@ impl Fractal {
             Debug::pop();
         }
 
+        // Settings in the top right corner
+        let size = vec2(300.0, 40.0);
+        let mut pos = vec2(self.viewport.size_in_pixels.x - size.x * 0.5 - 20.0, 40.0);
+
+        if self.coloring.light.enabled {
+            Debug::push("ui.light()");
+            let mut light = self.coloring.light;
+
+            let pi = std::f64::consts::PI;
+            for (label, value, min, max) in [
+                ("angle", &mut light.angle, -pi, pi),
+                ("height", &mut light.height, 0.0, 4.0),
+                ("ambient", &mut light.ambient, 0.0, 1.0),
+            ] {
+                let rect = Rect::center_size(pos, size);
+                *value = do_slider(state, rect, label, *value, min, max);
+                pos.y += size.y * 1.5;
+            }
+
+            self.coloring.set_light(light);
+            Debug::pop();
+        }
+
+        if self.coloring.mode == ColorMode::Trap {
+            Debug::push("ui.trap()");
+            let mut trap = self.trap;
+
+            let rect = Rect::center_size(pos, size);
+            if do_button(state, trap.shape.name(), 26., rect) {
+                trap.shape = trap.shape.next();
+            }
+            pos.y += size.y * 1.5;
+
+            let pi = std::f64::consts::PI;
+            for (label, value, min, max) in [
+                ("x", &mut trap.center.x, -2.0, 2.0),
+                ("y", &mut trap.center.y, -2.0, 2.0),
+                ("angle", &mut trap.angle, -pi, pi),
+                ("radius", &mut trap.radius, 0.0, 2.0),
+            ] {
+                let rect = Rect::center_size(pos, size);
+                *value = do_slider(state, rect, label, *value, min, max);
+                pos.y += size.y * 1.5;
+            }
+
+            if trap != self.trap {
+                self.trap = trap;
+                self.trap_changed = true;
+            }
+
+            // Rebuilding all tiles is not cheap, so wait until the user lets go of the slider
+            if self.trap_changed && !input.mouse_down {
+                self.trap_changed = false;
+                recreate_builder = true;
+            }
+            Debug::pop();
+        }
+
         // update tile builder cache
         Debug::push("builder.update()");
         self.builder.update();
@@ -242,13 +847,280 @@ impl Fractal {
             state.debug.print(&rate);
         }
 
+        if let Some(previous) = &mut self.previous_builder {
+            previous.update();
+        }
+
+        // Add the view to the history once it stopped moving
+        let moving = self.viewport.is_flying() || self.playback.is_some();
+        let entry = self.history_entry();
+        self.history.settle(entry, input.dt as f64, moving);
+
         if recreate_builder {
-            self.builder = TileBuilder::new(state.gpu.device(), &mut state.asset, &self.steps);
+            self.finder.clear();
+            self.viewport.domain = TileBuilder::domain(&self.steps);
+
+            let builder = TileBuilder::new(
+                state.gpu.device(),
+                &mut state.asset,
+                &self.steps,
+                &self.trap,
+                self.sampling,
+                self.iter_count,
+                self.cache_budget,
+                self.settings.disk_cache.then_some(DEFAULT_DISK_CACHE_LIMIT),
+                &self.settings.workers,
+            );
+
+            // Keep showing the old tiles until they are replaced. When switching again before
+            // anything was built, the tiles of the formula before are more useful.
+            let mut old = std::mem::replace(&mut self.builder, builder);
+            if self.has_visible_tiles || self.previous_builder.is_none() {
+                old.shutdown();
+                old.set_cache_budget(0);
+                self.previous_builder = Some(old);
+            }
         }
 
         Debug::pop();
     }
 
+    /// Colouring hotkeys, these only recolour the tiles
+    fn coloring_keys(&mut self, input: &Input) {
+        if input.key_click(VirtualKeyCode::C) {
+            self.coloring.set_mode(self.coloring.mode.next());
+        }
+
+        if input.key_click(VirtualKeyCode::V) {
+            self.coloring.set_interior(self.coloring.interior.next());
+        }
+
+        if input.key_click(VirtualKeyCode::O) {
+            self.coloring.set_outline(!self.coloring.outline);
+        }
+
+        // Not wrapped around, so keyframes interpolate the palette in the direction it was shifted
+        let mut palette_shift = 0.0;
+        if input.key(VirtualKeyCode::Comma) {
+            palette_shift -= PALETTE_SPEED;
+        }
+        if input.key(VirtualKeyCode::Period) {
+            palette_shift += PALETTE_SPEED;
+        }
+        if palette_shift != 0.0 {
+            let offset = self.coloring.palette_offset + palette_shift * input.dt as f64;
+            self.coloring.set_palette_offset(offset);
+        }
+
+        if input.key_click(VirtualKeyCode::B) {
+            let mut light = self.coloring.light;
+            light.enabled = !light.enabled;
+            self.coloring.set_light(light);
+        }
+    }
+
+    /// Change the samples and iterations per pixel, returns true if the builder has to be recreated
+    fn sampling_keys(&mut self, input: &Input) -> bool {
+        let mut recreate_builder = false;
+
+        if input.key_click(VirtualKeyCode::M) {
+            self.sampling = if input.shift() {
+                self.sampling.more_samples()
+            } else {
+                self.sampling.next()
+            };
+            recreate_builder = true;
+        }
+
+        if input.key_click(VirtualKeyCode::J) {
+            self.iter_count = (self.iter_count / 2).max(MIN_ITER_COUNT);
+            recreate_builder = true;
+        }
+
+        if input.key_click(VirtualKeyCode::L) {
+            self.iter_count = (self.iter_count * 2).min(MAX_ITER_COUNT);
+            recreate_builder = true;
+        }
+
+        recreate_builder
+    }
+
+    /// Edit and preview the camera path, returns true if the builder has to be recreated
+    fn path_keys(&mut self, input: &Input) -> bool {
+        let mut recreate_builder = false;
+
+        if input.key_click(VirtualKeyCode::F) {
+            let location = self.viewport.location();
+            let palette_offset = self.coloring.palette_offset;
+            self.path.push(location, palette_offset, self.iter_count);
+            self.path_time = self.path.duration();
+        }
+
+        if input.key_click(VirtualKeyCode::Insert) {
+            self.path.insert(Keyframe {
+                time: self.path_time,
+                location: self.viewport.location(),
+                palette_offset: self.coloring.palette_offset,
+                iter_count: self.iter_count,
+            });
+        }
+
+        if input.key_click(VirtualKeyCode::Delete) {
+            self.path.remove(self.path_time);
+        }
+
+        // Go to the keyframes, to change or remove them
+        let step = if input.key_click(VirtualKeyCode::PageUp) {
+            self.path.previous(self.path_time)
+        } else if input.key_click(VirtualKeyCode::PageDown) {
+            self.path.next(self.path_time)
+        } else {
+            None
+        };
+        if let Some(time) = step {
+            self.playback = None;
+            self.path_time = time;
+            if let Some(key) = self.path.at(time) {
+                self.viewport.fly_to(key.location);
+                recreate_builder |= self.show_keyframe(&key);
+            }
+        }
+
+        if input.key_click(VirtualKeyCode::Back) {
+            self.path.pop();
+        }
+
+        if input.key_click(VirtualKeyCode::P) {
+            // Continue where we are, or start over at the end
+            let start = if self.path_time < self.path.duration() {
+                self.path_time
+            } else {
+                0.0
+            };
+            self.playback = match self.playback {
+                None if !self.path.is_empty() => Some(start),
+                _ => None,
+            };
+        }
+
+        recreate_builder
+    }
+
+    /// Undo and redo, returns true if the builder has to be recreated
+    fn history_keys(&mut self, input: &Input) -> bool {
+        let mut recreate_builder = false;
+
+        let undo = input.ctrl() && input.key_click(VirtualKeyCode::Z) && !input.shift();
+        if undo || input.mouse_back {
+            if let Some(entry) = self.history.undo(self.history_entry()) {
+                recreate_builder |= self.go_to(entry);
+            }
+        }
+
+        let redo = input.ctrl() && input.key_click(VirtualKeyCode::Z) && input.shift();
+        let redo = redo || (input.ctrl() && input.key_click(VirtualKeyCode::Y));
+        if redo || input.mouse_forward {
+            if let Some(entry) = self.history.redo() {
+                recreate_builder |= self.go_to(entry);
+            }
+        }
+
+        recreate_builder
+    }
+
+    /// Save or load the state, and change the settings of the builder. Returns true if the
+    /// builder has to be recreated
+    fn settings_keys(&mut self, input: &Input) -> bool {
+        let mut recreate_builder = false;
+
+        if input.key_click(VirtualKeyCode::Key5) {
+            if let Err(e) = self.save() {
+                eprintln!("Could not save the state: {}", e);
+            }
+        }
+
+        if input.key_click(VirtualKeyCode::Key6) {
+            self.history.record(self.history_entry());
+            match self.load() {
+                Ok(()) => recreate_builder = true,
+                Err(e) => eprintln!("Could not load the state: {}", e),
+            }
+        }
+
+        if input.key_click(VirtualKeyCode::Key7) {
+            self.settings.disk_cache = !self.settings.disk_cache;
+            recreate_builder = true;
+
+            let mut saved = Settings::load();
+            saved.disk_cache = self.settings.disk_cache;
+            if let Err(e) = saved.save() {
+                eprintln!("Could not save the settings: {}", e);
+            }
+        }
+
+        if input.key_click(VirtualKeyCode::Key8) {
+            let mut workers = self.settings.workers;
+            workers.background = !workers.background;
+            self.set_worker_config(workers);
+
+            // Don't save the command line overrides
+            let mut saved = Settings::load();
+            saved.workers.background = workers.background;
+            if let Err(e) = saved.save() {
+                eprintln!("Could not save the settings: {}", e);
+            }
+        }
+
+        if std::mem::take(&mut self.workers_changed) {
+            recreate_builder = true;
+        }
+
+        if input.key_click(VirtualKeyCode::LBracket) {
+            self.cache_budget = (self.cache_budget / 2).max(MIN_CACHE_BUDGET);
+            self.builder.set_cache_budget(self.cache_budget);
+        }
+
+        if input.key_click(VirtualKeyCode::RBracket) {
+            self.cache_budget *= 2;
+            self.builder.set_cache_budget(self.cache_budget);
+        }
+
+        recreate_builder
+    }
+
+    /// Toggle the windows and tools on top of the view, reset its rotation or export it
+    fn view_keys(&mut self, input: &Input) {
+        if input.key_click(VirtualKeyCode::G) {
+            self.bookmarks.visible = !self.bookmarks.visible;
+        }
+
+        if input.key_click(VirtualKeyCode::X) {
+            self.inspector.enabled = !self.inspector.enabled;
+        }
+
+        if input.key_click(VirtualKeyCode::T) {
+            if input.shift() {
+                let (steps, viewport) = (&self.steps, &self.viewport);
+                self.finder.search_misiurewicz(steps, viewport, input.mouse);
+            } else {
+                let (steps, viewport, n) = (&self.steps, &self.viewport, self.iter_count);
+                self.finder.search(steps, viewport, n, input.mouse);
+            }
+        }
+
+        if input.key_click(VirtualKeyCode::Tab) {
+            self.minimap.visible = !self.minimap.visible;
+        }
+
+        if input.key_click(VirtualKeyCode::R) {
+            self.viewport.rotation = 0.0;
+        }
+
+        if input.key_click(VirtualKeyCode::F12) {
+            self.export_pending = true;
+        }
+    }
+
     pub fn distance(scale: f64) -> String {
         let mut result = String::new();
         let scales = [
@@ -295,15 +1167,85 @@ impl Fractal {
     }
 }
 
+/// Return this tile, or else the closest ancestor, if the builder has any of them
+fn closest_tile(builder: &mut TileBuilder, p: Option<TilePos>) -> Option<(TilePos, &mut Tile)> {
+    let mut p = p;
+    while let Some(q) = p {
+        if builder.has_tile(&q) {
+            return Some((q, builder.tile(&q).unwrap()));
+        }
+        p = q.parent();
+    }
+    None
+}
+
+fn do_button(state: &mut State, text: &str, text_size: f32, rect: Rect) -> bool {
+    let region = state.ui.region(&rect);
+    let image_back = state.asset.image("button_back.png");
+
+    state.gpu.blit(&rect, &image_back);
+    state.asset.text(
+        FontType::Normal,
+        rect.center().map(|x| x as _),
+        V2 {
+            x: TextAlignment::Center,
+            y: TextAlignment::Center,
+        },
+        text_size,
+        &mut state.gpu,
+        text,
+    );
+
+    let image_front = state.asset.image(if region.down {
+        "button_front_down.png"
+    } else if region.hover {
+        "button_front_hot.png"
+    } else {
+        "button_front_norm.png"
+    });
+
+    state.gpu.blit(&rect, &image_front);
+    region.click
+}
+
+/// A labeled slider for values between `min` and `max`
+fn do_slider(state: &mut State, rect: Rect, label: &str, value: f64, min: f64, max: f64) -> f64 {
+    state.asset.text(
+        FontType::Normal,
+        vec2(rect.min.x - 10.0, rect.center().y).map(|x| x as _),
+        V2 {
+            x: TextAlignment::Right,
+            y: TextAlignment::Center,
+        },
+        26.,
+        &mut state.gpu,
+        label,
+    );
+
+    let t = (value - min) / (max - min);
+    let t_new = state.ui.slider(&mut state.gpu, &mut state.asset, &rect, t);
+
+    // don't introduce rounding errors when nothing changed
+    if t_new == t {
+        value
+    } else {
+        min + t_new * (max - min)
+    }
+}
+
 pub struct MappedInput {
     dir: V2,
     zoom: f64,
+
+    /// Radians per second, counter clockwise
+    rotate: f64,
 }
 
 fn map_input(input: &Input) -> MappedInput {
     let mut dir: V2<f64> = vec2(0.0, 0.0);
     let mut speed = 1.0;
     let mut zoom = 0.0;
+    let mut rotate = 0.0;
     for k in input.keys_down.iter() {
         match k {
             VirtualKeyCode::W => dir.y += 1.0,
@@ -321,10 +1263,14 @@ fn map_input(input: &Input) -> MappedInput {
 
             VirtualKeyCode::I => zoom += 1.0,
             VirtualKeyCode::K => zoom -= 1.0,
+
+            VirtualKeyCode::Q => rotate += 1.0,
+            VirtualKeyCode::E => rotate -= 1.0,
             _ => (),
         }
     }
     let dir = dir / dir.magnitude().max(1.0) * speed * 1.0;
     let zoom = zoom * speed * 4.0;
-    MappedInput { dir, zoom }
+    let rotate = rotate * speed;
+    MappedInput { dir, zoom, rotate }
 }
@@ -67,7 +67,7 @@ impl GlyphCache {
                 glyph.draw(|x, y, v| {
                     let ix = (y as usize * bb.width() as usize + x as usize) * 4;
                     let v = (v * 255.0).round() as u8;
-                    data[ix + 0] = v;
+                    data[ix] = v;
                     data[ix + 1] = v;
                     data[ix + 2] = v;
                     data[ix + 3] = v;
@@ -57,8 +57,10 @@ impl GpuDevice {
         }))
         .unwrap();
 
-        let mut limits = Limits::default();
-        limits.max_texture_array_layers = 1024;
+        let limits = Limits {
+            max_texture_array_layers: 1024,
+            ..Limits::default()
+        };
 
         // device, logical handle to the adapter.
         // TODO: setup, and figure out how tracing works.
@@ -106,9 +108,39 @@ impl Gpu {
         self.draw_ui.blit(&self.device, rect, img);
     }
 
-    pub fn tile(&mut self, vp: &Viewport, p: &TilePos, img: &Image) {
-        let rect = vp.world_to_screen_rect(&p.square());
-        self.draw_tiles.blit(&self.device, &rect, img);
+    /// Draw a line of `width` pixels, stretching the image along it
+    pub fn line(&mut self, a: V2, b: V2, width: f64, img: &Image) {
+        let d = b - a;
+        let length = d.magnitude();
+        if length <= 0.0 {
+            return;
+        }
+
+        // Perpendicular to the line, half the width long
+        let n = V2::new(-d.y, d.x) * (0.5 * width / length);
+        let quad = [a - n, b - n, a + n, b + n].map(|p| p.map(|x| x as f32));
+        self.draw_ui.blit_quad(&self.device, &quad, img);
+    }
+
+    /// Draw an entire tile image to a quad on the screen, see `DrawTiles::blit` for the order of
+    /// the corners
+    pub fn tile_quad(&mut self, quad: &[V2<f32>; 4], img: &Image, color: [f32; 4]) {
+        let uv = Rect::min_max(V2::zero(), V2::new(1.0, 1.0));
+        self.draw_tiles.blit(&self.device, quad, img, &uv, color);
+    }
+
+    /// Draw tile `p`, using the image of tile `src`, which is either `p` or one of its ancestors.
+    /// The image is multiplied by `color`.
+    #[rustfmt::skip]
+    pub fn tile(&mut self, vp: &Viewport, p: &TilePos, src: &TilePos, img: &Image, color: [f32; 4]) {
+        let dst = p.square();
+        let src = src.square();
+        let quad = vp.world_to_screen_quad(&dst);
+        let uv = Rect::min_max(
+            (dst.corner_min() - src.corner_min()) / src.size().x,
+            (dst.corner_max() - src.corner_min()) / src.size().x,
+        );
+        self.draw_tiles.blit(&self.device, &quad, img, &uv, color);
     }
 
     pub fn next_frame(&mut self, resolution: V2<u32>) -> (SurfaceTexture, TextureView) {
@@ -4,42 +4,104 @@ use wgpu::*;
 
 use crate::asset_loader::AssetLoader;
 use crate::fractal::FractalStep;
+use crate::fractal::OrbitTrap;
+use crate::fractal::TrapShape;
 use crate::gpu::GpuDevice;
 use crate::gpu::ShaderLoader;
-use crate::image::Image;
+use crate::tile_data::Sample;
+use crate::tile_data::TileData;
 use crate::tilemap::TilePos;
 use crate::util::*;
 
 const TILE_SIZE: u32 = 256;
 
+/// The tile is rendered as raw samples, spread over a few rgba f32 targets
+const TARGET_COUNT: usize = 2;
+const TEXEL_SIZE: u32 = 4 * 4;
+const TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+
 pub struct ComputeTile {
     pipeline: RenderPipeline,
     vertex_buffer: Buffer,
 
-    texture: Texture,
-    texture_view: TextureView,
-    buffer: Buffer,
+    targets: Vec<Target>,
 
     bind_group_layout: BindGroupLayout,
     bind_group: BindGroup,
 }
 
+/// Texture we render to, and the buffer we copy it to for reading it back
+struct Target {
+    texture: Texture,
+    texture_view: TextureView,
+    buffer: Buffer,
+}
+
 impl ComputeTile {
-    pub fn load(alg: &[FractalStep], device: &GpuDevice, asset_loader: &mut AssetLoader) -> Self {
+    pub fn load(
+        alg: &[FractalStep],
+        trap: &OrbitTrap,
+        iter_count: u32,
+        device: &GpuDevice,
+        asset_loader: &mut AssetLoader,
+    ) -> Self {
         let source = asset_loader.text_file("shader/compute_tile.wgsl");
         let source = source.replace("REAL", "f32");
 
+        // Each step also updates the derivative dz/dc
         #[rustfmt::skip]
         let implementation = alg.iter().map(|x| match x {
-            FractalStep::Conj   => "z.y = -z.y;\n",
-            FractalStep::AbsR   => "z.x = abs(z.x);\n",
-            FractalStep::AbsI   => "z.y = -abs(z.y);\n",
-            FractalStep::Square => "z = cpx_sqr(z);\n",
-            FractalStep::Cube   => "z = cpx_cube(z);\n",
-            FractalStep::AddC   => "z = z + c;\nt = t + 1.0;\n",
+            FractalStep::Conj   => "z.y = -z.y;\ndz.y = -dz.y;\n",
+            FractalStep::AbsR   => "if (z.x < 0.0) { z.x = -z.x; dz.x = -dz.x; }\n",
+            FractalStep::AbsI   => "if (z.y > 0.0) { z.y = -z.y; dz.y = -dz.y; }\n",
+            FractalStep::Square => "dz = cpx_mul(z, dz) * 2.0;\nz = cpx_sqr(z);\n",
+            FractalStep::Cube   => "dz = cpx_mul(cpx_sqr(z), dz) * 3.0;\nz = cpx_cube(z);\n",
+            FractalStep::AddC   => "z = z + c;\ndz.x = dz.x + 1.0;\nt = t + 1.0;\n",
+        }).collect::<String>();
+
+        // The same steps, but for the interior distance estimate.
+        // d1 holds dz/dz0 and dz/dc, d2 holds the derivatives of those with respect to z0.
+        #[rustfmt::skip]
+        let interior = alg.iter().map(|x| match x {
+            FractalStep::Conj   => "z.y = -z.y;\nd1 = d1 * flip_y;\nd2 = d2 * flip_y;\n",
+            FractalStep::AbsR   => "if (z.x < 0.0) { z.x = -z.x; d1 = d1 * flip_x; d2 = d2 * flip_x; }\n",
+            FractalStep::AbsI   => "if (z.y > 0.0) { z.y = -z.y; d1 = d1 * flip_y; d2 = d2 * flip_y; }\n",
+            FractalStep::Square => concat!(
+                "d2 = vec4<f32>(2.0 * (cpx_sqr(d1.xy) + cpx_mul(z, d2.xy)), 2.0 * (cpx_mul(d1.xy, d1.zw) + cpx_mul(z, d2.zw)));\n",
+                "d1 = vec4<f32>(2.0 * cpx_mul(z, d1.xy), 2.0 * cpx_mul(z, d1.zw));\n",
+                "z = cpx_sqr(z);\n",
+            ),
+            FractalStep::Cube   => concat!(
+                "{\nlet z2 = cpx_sqr(z);\n",
+                "d2 = vec4<f32>(6.0 * cpx_mul(z, cpx_sqr(d1.xy)) + 3.0 * cpx_mul(z2, d2.xy), 6.0 * cpx_mul(z, cpx_mul(d1.xy, d1.zw)) + 3.0 * cpx_mul(z2, d2.zw));\n",
+                "d1 = vec4<f32>(3.0 * cpx_mul(z2, d1.xy), 3.0 * cpx_mul(z2, d1.zw));\n",
+                "z = cpx_cube(z);\n}\n",
+            ),
+            FractalStep::AddC   => "z = z + c;\nd1.z = d1.z + 1.0;\n",
         }).collect::<String>();
 
-        let source = source.replace("@IMPL@", &implementation);
+        let source = source
+            .replace("@IMPL@", &implementation)
+            .replace("@IMPL_INTERIOR@", &interior)
+            .replace("@ITER_COUNT@", &iter_count.to_string());
+
+        // The trap is baked into the shader, `p` is the position relative to the trap
+        #[rustfmt::skip]
+        let trap_distance = match trap.shape {
+            TrapShape::Point  => "length(p)".to_string(),
+            TrapShape::Line   => "abs(p.y)".to_string(),
+            TrapShape::Cross  => "min(abs(p.x), abs(p.y))".to_string(),
+            TrapShape::Circle => format!("abs(length(p) - {:.10})", trap.radius),
+        };
+
+        let (sin, cos) = trap.angle.sin_cos();
+        let trap_center = format!("{:.10}, {:.10}", trap.center.x, trap.center.y);
+        let trap_rotation = format!("{:.10}, {:.10}", cos, sin);
+
+        let source = source
+            .replace("@TRAP_CENTER@", &trap_center)
+            .replace("@TRAP_ROTATION@", &trap_rotation)
+            .replace("@TRAP@", &trap_distance);
 
         let shader = ShaderLoader::compile(&device.device, &source).unwrap();
 
@@ -50,29 +112,39 @@ impl ComputeTile {
             usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
         });
 
-        let copy_buffer = device.device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: TILE_SIZE as u64 * TILE_SIZE as u64 * 4,
-            mapped_at_creation: false,
-            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
-        });
+        let targets = (0..TARGET_COUNT)
+            .map(|_| {
+                let buffer = device.device.create_buffer(&BufferDescriptor {
+                    label: None,
+                    size: TILE_SIZE as u64 * TILE_SIZE as u64 * TEXEL_SIZE as u64,
+                    mapped_at_creation: false,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                });
+
+                // Texture
+                let texture = device.device.create_texture(&TextureDescriptor {
+                    label: None,
+                    mip_level_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: TEXTURE_FORMAT,
+                    usage: TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT,
+                    sample_count: 1,
+                    size: Extent3d {
+                        width: TILE_SIZE,
+                        height: TILE_SIZE,
+                        depth_or_array_layers: 1,
+                    },
+                });
 
-        // Texture
-        let texture = device.device.create_texture(&TextureDescriptor {
-            label: None,
-            mip_level_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Bgra8UnormSrgb,
-            usage: TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT,
-            sample_count: 1,
-            size: Extent3d {
-                width: TILE_SIZE,
-                height: TILE_SIZE,
-                depth_or_array_layers: 1,
-            },
-        });
+                let texture_view = texture.create_view(&TextureViewDescriptor::default());
 
-        let texture_view = texture.create_view(&TextureViewDescriptor::default());
+                Target {
+                    texture,
+                    texture_view,
+                    buffer,
+                }
+            })
+            .collect::<Vec<_>>();
 
         #[rustfmt::skip]
         let bind_group_layout = device.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -93,6 +165,15 @@ impl ComputeTile {
             push_constant_ranges: &[],
         });
 
+        let color_targets = vec![
+            ColorTargetState {
+                format: TEXTURE_FORMAT,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            };
+            TARGET_COUNT
+        ];
+
         #[rustfmt::skip]
         let pipeline = device.device.create_render_pipeline(&RenderPipelineDescriptor {
             label: None,
@@ -109,11 +190,7 @@ impl ComputeTile {
             fragment: Some(FragmentState {
                 module: &shader,
                 entry_point: "fs_main",
-                targets: &[ColorTargetState {
-                    format: device.swap_chain_format,
-                    blend: None,
-                    write_mask: ColorWrites::ALL,
-                }],
+                targets: &color_targets,
             }),
             primitive: PrimitiveState::default(),
             depth_stencil: None,
@@ -123,19 +200,27 @@ impl ComputeTile {
         ComputeTile {
             pipeline,
             vertex_buffer,
-            buffer: copy_buffer,
-            texture,
-            texture_view,
+            targets,
             bind_group_layout,
             bind_group,
         }
     }
 
-    pub fn build(&self, device: &GpuDevice, p: &TilePos) -> Image {
+    pub fn build(&self, device: &GpuDevice, p: &TilePos) -> TileData {
+        let samples = self.render(device, p, V2::new(0.5, 0.5));
+        TileData::new(V2::new(TILE_SIZE, TILE_SIZE), samples)
+    }
+
+    /// Render the samples of a tile, `offset` is the sample position inside the pixels, from 0
+    /// to 1. The pixels are sampled at the centre with an offset of 0.5.
+    pub fn render(&self, device: &GpuDevice, p: &TilePos, offset: V2) -> Vec<Sample> {
         let rect = p.square();
 
-        let min = rect.corner_min();
-        let max = rect.corner_max();
+        // The vertices are at the corners of the tile, so this moves every sample
+        let pixel_size = rect.size().x / TILE_SIZE as f64;
+        let shift = (offset - V2::new(0.5, 0.5)) * pixel_size;
+        let min = rect.corner_min() + shift;
+        let max = rect.corner_max() + shift;
 
         #[rustfmt::skip]
         let vertex_list = [
@@ -157,16 +242,22 @@ impl ComputeTile {
         let mut encoder = device.device.create_command_encoder(&CommandEncoderDescriptor { label: None });
 
         {
-            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: None,
-                color_attachments: &[RenderPassColorAttachment {
-                    view: &self.texture_view,
+            let color_attachments = self
+                .targets
+                .iter()
+                .map(|t| RenderPassColorAttachment {
+                    view: &t.texture_view,
                     resolve_target: None,
                     ops: Operations {
                         load: LoadOp::Clear(Color::BLACK),
                         store: true,
                     },
-                }],
+                })
+                .collect::<Vec<_>>();
+
+            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: None,
+                color_attachments: &color_attachments,
                 depth_stencil_attachment: None,
             });
 
@@ -176,40 +267,75 @@ impl ComputeTile {
             rpass.draw(0..6, 0..1);
         }
 
-        encoder.copy_texture_to_buffer(
-            ImageCopyTexture {
-                texture: &self.texture,
-                mip_level: 0,
-                origin: Origin3d::ZERO,
-                aspect: TextureAspect::All,
-            },
-            ImageCopyBuffer {
-                buffer: &self.buffer,
-                layout: ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(NonZeroU32::new(4 * TILE_SIZE).unwrap()),
-                    rows_per_image: Some(NonZeroU32::new(TILE_SIZE).unwrap()),
+        for t in self.targets.iter() {
+            encoder.copy_texture_to_buffer(
+                ImageCopyTexture {
+                    texture: &t.texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
                 },
-            },
-            Extent3d {
-                width: TILE_SIZE,
-                height: TILE_SIZE,
-                depth_or_array_layers: 1,
-            },
-        );
+                ImageCopyBuffer {
+                    buffer: &t.buffer,
+                    layout: ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(NonZeroU32::new(TEXEL_SIZE * TILE_SIZE).unwrap()),
+                        rows_per_image: Some(NonZeroU32::new(TILE_SIZE).unwrap()),
+                    },
+                },
+                Extent3d {
+                    width: TILE_SIZE,
+                    height: TILE_SIZE,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
         device.queue.submit(Some(encoder.finish()));
 
-        let image = {
-            let slice = self.buffer.slice(..);
-            let fut = slice.map_async(MapMode::Read);
+        let samples = {
+            let slices = self
+                .targets
+                .iter()
+                .map(|t| t.buffer.slice(..))
+                .collect::<Vec<_>>();
+            let futures = slices
+                .iter()
+                .map(|s| s.map_async(MapMode::Read))
+                .collect::<Vec<_>>();
             device.device.poll(wgpu::Maintain::Wait);
-            pollster::block_on(fut).unwrap();
-            let bytes = slice.get_mapped_range();
-            Image::new(V2::new(TILE_SIZE, TILE_SIZE), bytes.to_vec())
+            for fut in futures {
+                pollster::block_on(fut).unwrap();
+            }
+
+            let bytes = slices
+                .iter()
+                .map(|s| s.get_mapped_range())
+                .collect::<Vec<_>>();
+
+            // channel 'c' of pixel 'i'
+            let channel = |i: usize, c: usize| {
+                let b = &bytes[c / 4][i * TEXEL_SIZE as usize + (c % 4) * 4..];
+                f32::from_ne_bytes([b[0], b[1], b[2], b[3]])
+            };
+
+            (0..(TILE_SIZE * TILE_SIZE) as usize)
+                .map(|i| Sample {
+                    iter: channel(i, 0),
+                    de: channel(i, 1),
+                    normal: channel(i, 2),
+                    trap_dist: channel(i, 3),
+                    trap_iter: channel(i, 4),
+                    z_abs: channel(i, 5),
+                    period: channel(i, 6),
+                    atom: channel(i, 7),
+                })
+                .collect::<Vec<_>>()
         };
 
-        self.buffer.unmap();
-        image
+        for t in self.targets.iter() {
+            t.buffer.unmap();
+        }
+        samples
     }
 }
 
@@ -158,7 +158,7 @@ impl DrawTiles {
                 entry_point: "fs_main",
                 targets: &[ColorTargetState {
                     format: device.swap_chain_format,
-                    blend: None,
+                    blend: Some(BlendState::ALPHA_BLENDING),
                     write_mask: ColorWrites::ALL,
                 }],
             }),
@@ -190,11 +190,20 @@ impl DrawTiles {
         }
     }
 
-    pub fn blit(&mut self, device: &GpuDevice, rect: &Rect, img: &Image) {
-        let lx = rect.corner_min().x as f32;
-        let ly = rect.corner_min().y as f32;
-        let hx = rect.corner_max().x as f32;
-        let hy = rect.corner_max().y as f32;
+    /// Draw the part `uv` of a tile image to `quad` on the screen.
+    ///
+    /// The corners of the quad are ordered like the corners of `uv`: min, (max.x, min.y),
+    /// (min.x, max.y), max. So the quad can be rotated.
+    ///
+    /// The image is multiplied with `color`, the alpha of the image itself is ignored.
+    #[rustfmt::skip]
+    pub fn blit(&mut self, device: &GpuDevice, quad: &[V2<f32>; 4], img: &Image, uv: &Rect, color: [f32; 4]) {
+        let [p0, p1, p2, p3] = *quad;
+
+        let ul = uv.corner_min().x as f32;
+        let vl = uv.corner_min().y as f32;
+        let uh = uv.corner_max().x as f32;
+        let vh = uv.corner_max().y as f32;
 
         assert_eq!(img.size().x, TILE_SIZE);
         assert_eq!(img.size().y, TILE_SIZE);
@@ -265,13 +274,13 @@ impl DrawTiles {
         if self.vertex_list.len() + 6 < MAX_VERTS as _ {
             #[rustfmt::skip]
             self.vertex_list.extend_from_slice(&[
-                Vertex { pos: V2::new(lx, ly), uv: V2::new(0.0, 0.0), ix, },
-                Vertex { pos: V2::new(hx, ly), uv: V2::new(1.0, 0.0), ix, },
-                Vertex { pos: V2::new(lx, hy), uv: V2::new(0.0, 1.0), ix, },
+                Vertex { pos: p0, uv: V2::new(ul, vl), ix, color },
+                Vertex { pos: p1, uv: V2::new(uh, vl), ix, color },
+                Vertex { pos: p2, uv: V2::new(ul, vh), ix, color },
 
-                Vertex { pos: V2::new(hx, ly), uv: V2::new(1.0, 0.0), ix, },
-                Vertex { pos: V2::new(hx, hy), uv: V2::new(1.0, 1.0), ix, },
-                Vertex { pos: V2::new(lx, hy), uv: V2::new(0.0, 1.0), ix, },
+                Vertex { pos: p1, uv: V2::new(uh, vl), ix, color },
+                Vertex { pos: p3, uv: V2::new(uh, vh), ix, color },
+                Vertex { pos: p2, uv: V2::new(ul, vh), ix, color },
             ]);
         } else {
             eprintln!("TOO MANY VERTS IN TILEMAP!");
@@ -327,6 +336,7 @@ pub struct Vertex {
     pos: V2<f32>,
     uv: V2<f32>,
     ix: i32,
+    color: [f32; 4],
 }
 
 unsafe impl bytemuck::Pod for Vertex {}
@@ -342,11 +352,12 @@ unsafe impl bytemuck::Pod for UniformData {}
 unsafe impl bytemuck::Zeroable for UniformData {}
 
 impl Vertex {
-    pub fn attrs() -> [VertexAttribute; 3] {
+    pub fn attrs() -> [VertexAttribute; 4] {
         vertex_attr_array![
             0 => Float32x2,
             1 => Float32x2,
             2 => Sint32,
+            3 => Float32x4,
         ]
     }
 }
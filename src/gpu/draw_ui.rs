@@ -215,11 +215,14 @@ impl DrawUI {
     }
 
     pub fn blit(&mut self, device: &GpuDevice, screen_rect: &Rect, img: &Image) {
-        let lx = screen_rect.corner_min().x as f32;
-        let ly = screen_rect.corner_min().y as f32;
-        let hx = screen_rect.corner_max().x as f32;
-        let hy = screen_rect.corner_max().y as f32;
+        let l = screen_rect.corner_min().map(|x| x as f32);
+        let h = screen_rect.corner_max().map(|x| x as f32);
+        self.blit_quad(device, &[l, V2::new(h.x, l.y), V2::new(l.x, h.y), h], img);
+    }
 
+    /// Draw an image to any quad on the screen, the corners are ordered min, (max.x, min.y),
+    /// (min.x, max.y), max, like the corners of the image
+    pub fn blit_quad(&mut self, device: &GpuDevice, quad: &[V2<f32>; 4], img: &Image) {
         // We don't free blocks yet, but we might in the future, just add a 'used' flag
         let atlas_rect = self.blocks.get(&img.id()).copied();
 
@@ -232,7 +235,7 @@ impl DrawUI {
                         // nope
                         self.blocks = BTreeMap::new();
                         self.pack = ShelfPack::new(V2::new(ATLAS_SIZE, ATLAS_SIZE));
-                        self.blit(device, screen_rect, img);
+                        self.blit_quad(device, quad, img);
                         return;
                     }
                 };
@@ -245,7 +248,7 @@ impl DrawUI {
                         origin: Origin3d {
                             x: rect.corner_min().x as u32,
                             y: rect.corner_min().y as u32,
-                            z: 0 as u32,
+                            z: 0,
                         },
                         aspect: TextureAspect::All,
                     },
@@ -262,7 +265,7 @@ impl DrawUI {
                     },
                 );
 
-                self.blocks.insert(img.id(), rect.clone());
+                self.blocks.insert(img.id(), rect);
 
                 rect
             }
@@ -273,16 +276,17 @@ impl DrawUI {
         let uv_l = rect.corner_min().map(|x| x as f32) / ATLAS_SIZE as f32;
         let uv_h = rect.corner_max().map(|x| x as f32) / ATLAS_SIZE as f32;
 
+        let [p0, p1, p2, p3] = *quad;
         if self.vertex_list.len() + 6 < MAX_VERTS as _ {
             #[rustfmt::skip]
             self.vertex_list.extend_from_slice(&[
-                Vertex { pos: V2::new(lx, ly), uv: V2::new(uv_l.x, uv_l.y), },
-                Vertex { pos: V2::new(hx, ly), uv: V2::new(uv_h.x, uv_l.y), },
-                Vertex { pos: V2::new(lx, hy), uv: V2::new(uv_l.x, uv_h.y), },
+                Vertex { pos: p0, uv: V2::new(uv_l.x, uv_l.y), },
+                Vertex { pos: p1, uv: V2::new(uv_h.x, uv_l.y), },
+                Vertex { pos: p2, uv: V2::new(uv_l.x, uv_h.y), },
 
-                Vertex { pos: V2::new(hx, ly), uv: V2::new(uv_h.x, uv_l.y), },
-                Vertex { pos: V2::new(hx, hy), uv: V2::new(uv_h.x, uv_h.y), },
-                Vertex { pos: V2::new(lx, hy), uv: V2::new(uv_l.x, uv_h.y), },
+                Vertex { pos: p1, uv: V2::new(uv_h.x, uv_l.y), },
+                Vertex { pos: p3, uv: V2::new(uv_h.x, uv_h.y), },
+                Vertex { pos: p2, uv: V2::new(uv_l.x, uv_h.y), },
             ]);
         } else {
             eprintln!("TOO MANY VERTS IN UI!");
@@ -67,19 +67,17 @@ impl ShaderLoader {
         let path = PathBuf::from(path);
         let mtime = path.metadata().unwrap().modified().unwrap();
 
-        if self.module.is_none() || mtime != self.mtime || self.path != path {
+        let changed = self.module.is_none() || mtime != self.mtime || self.path != path;
+        if changed {
             let source = std::fs::read_to_string(&path).unwrap();
             self.mtime = mtime;
             self.path = path;
 
-            match Self::compile(device, &source) {
-                Some(module) => self.module = Some(module),
-                None => (),
-            };
-
-            (self.module.as_ref().unwrap(), true)
-        } else {
-            (self.module.as_ref().unwrap(), false)
+            if let Some(module) = Self::compile(device, &source) {
+                self.module = Some(module);
+            }
         }
+
+        (self.module.as_ref().unwrap(), changed)
     }
 }
@@ -0,0 +1,166 @@
+use crate::fractal::FractalStep;
+use crate::viewport::Location;
+
+/// Older entries are forgotten
+const MAX_ENTRIES: usize = 256;
+
+/// The view is added to the history when it did not change for this many seconds
+const SETTLE_TIME: f64 = 0.5;
+
+/// A view and formula that can be returned to
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub location: Location,
+    pub steps: Vec<FractalStep>,
+}
+
+/// Undo and redo for the view and the formula
+///
+/// Navigation is continuous, so the view is only recorded once it stopped moving. Destructive
+/// changes like editing the formula should be recorded right before they happen.
+pub struct History {
+    entries: Vec<HistoryEntry>,
+
+    /// The entry we are at, entries after this can be redone
+    index: usize,
+
+    /// The view and formula of the last frame, and for how long they did not change
+    settled: Option<(HistoryEntry, f64)>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            entries: Vec::new(),
+            index: 0,
+            settled: None,
+        }
+    }
+
+    /// Call this every frame, the view is recorded once it did not change for a while.
+    ///
+    /// All changes before that are merged into one entry, `moving` delays recording it.
+    pub fn settle(&mut self, entry: HistoryEntry, dt: f64, moving: bool) {
+        match &mut self.settled {
+            Some((last, time)) if *last == entry => {
+                *time += dt;
+                if *time >= SETTLE_TIME && !moving {
+                    self.record(entry);
+                }
+            }
+            _ => self.settled = Some((entry, 0.0)),
+        }
+    }
+
+    /// Add a new entry, this drops everything that could be redone.
+    ///
+    /// Does nothing if we are already at this entry.
+    pub fn record(&mut self, entry: HistoryEntry) {
+        if self.entries.get(self.index) == Some(&entry) {
+            return;
+        }
+
+        if !self.entries.is_empty() {
+            self.entries.truncate(self.index + 1);
+        }
+        self.entries.push(entry);
+
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.index = self.entries.len() - 1;
+    }
+
+    /// Go back, `current` is recorded first if it was not yet, so it can be redone
+    pub fn undo(&mut self, current: HistoryEntry) -> Option<HistoryEntry> {
+        self.record(current);
+        self.index = self.index.checked_sub(1)?;
+        Some(self.entries[self.index].clone())
+    }
+
+    pub fn redo(&mut self) -> Option<HistoryEntry> {
+        let entry = self.entries.get(self.index + 1)?.clone();
+        self.index += 1;
+        Some(entry)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.index > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.index + 1 < self.entries.len()
+    }
+}
+
+#[cfg(test)]
+fn test_entry(zoom: f64) -> HistoryEntry {
+    use crate::util::V2;
+
+    HistoryEntry {
+        location: Location {
+            offset: V2::new(0.0, 0.0),
+            zoom,
+            rotation: 0.0,
+        },
+        steps: Vec::new(),
+    }
+}
+
+#[test]
+fn test_undo_redo() {
+    let mut h = History::new();
+    h.record(test_entry(1.0));
+    h.record(test_entry(2.0));
+    assert!(h.can_undo());
+    assert!(!h.can_redo());
+
+    // The current view is recorded, so it can be redone
+    assert_eq!(h.undo(test_entry(3.0)), Some(test_entry(2.0)));
+    assert_eq!(h.undo(test_entry(2.0)), Some(test_entry(1.0)));
+    assert_eq!(h.undo(test_entry(1.0)), None);
+    assert!(!h.can_undo());
+
+    assert_eq!(h.redo(), Some(test_entry(2.0)));
+    assert_eq!(h.redo(), Some(test_entry(3.0)));
+    assert_eq!(h.redo(), None);
+}
+
+#[test]
+fn test_record_clears_redo() {
+    let mut h = History::new();
+    h.record(test_entry(1.0));
+    h.record(test_entry(2.0));
+    assert_eq!(h.undo(test_entry(2.0)), Some(test_entry(1.0)));
+    assert!(h.can_redo());
+
+    h.record(test_entry(4.0));
+    assert!(!h.can_redo());
+    assert_eq!(h.redo(), None);
+    assert_eq!(h.undo(test_entry(4.0)), Some(test_entry(1.0)));
+}
+
+#[test]
+fn test_settle() {
+    let mut h = History::new();
+
+    // Changes within the settle time are merged into one entry
+    for i in 0..10 {
+        h.settle(test_entry(i as f64), 0.1, false);
+    }
+    assert!(!h.can_undo() && h.entries.is_empty());
+
+    for _ in 0..10 {
+        h.settle(test_entry(9.0), 0.1, false);
+    }
+    assert_eq!(h.entries, vec![test_entry(9.0)]);
+
+    // Nothing is recorded while moving
+    for _ in 0..10 {
+        h.settle(test_entry(10.0), 0.1, true);
+    }
+    assert_eq!(h.entries.len(), 1);
+
+    h.settle(test_entry(10.0), 0.1, false);
+    assert_eq!(h.entries, vec![test_entry(9.0), test_entry(10.0)]);
+}
@@ -0,0 +1,137 @@
+use crate::asset_loader::FontType;
+use crate::asset_loader::TextAlignment;
+use crate::fractal::FractalStep;
+use crate::image::Image;
+use crate::orbit::Orbit;
+use crate::orbit::OrbitEnd;
+use crate::state::State;
+use crate::update_loop::Input;
+use crate::util::*;
+use crate::viewport::Viewport;
+
+/// Only the start of long orbits is drawn, the ui has a limited number of vertices
+const MAX_DRAWN_POINTS: usize = 256;
+
+const LINE_WIDTH: f64 = 2.0;
+const MARKER_SIZE: f64 = 6.0;
+
+/// Distance between the cursor and the information panel
+const PANEL_OFFSET: f64 = 24.0;
+
+const TEXT_SIZE: f32 = 22.0;
+
+/// Shows the orbit of the point under the cursor, and what happened to it
+///
+/// The orbit is computed on the cpu with the same escape radius and period detection as the
+/// tile builders, so the numbers explain the color of the pixel.
+pub struct Inspector {
+    pub enabled: bool,
+
+    /// The orbit of the last frame, it is only computed again when the point moves
+    orbit: Option<Orbit>,
+
+    line: Image,
+    marker: Image,
+}
+
+impl Inspector {
+    pub fn new() -> Self {
+        Inspector {
+            enabled: false,
+            orbit: None,
+            line: solid_image([255, 220, 60, 255]),
+            marker: solid_image([255, 80, 60, 255]),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        state: &mut State,
+        viewport: &Viewport,
+        steps: &[FractalStep],
+        iter_count: u32,
+        input: &Input,
+    ) {
+        if !self.enabled {
+            self.orbit = None;
+            return;
+        }
+
+        // Nothing to inspect below the ui
+        if state.ui.has_input() {
+            return;
+        }
+
+        let mouse = input.mouse.map(|x| x as f64);
+        let c = viewport.screen_to_world_exact(mouse);
+        let outdated = |o: &Orbit| o.c != c || o.iter_count != iter_count;
+        if self.orbit.as_ref().is_none_or(outdated) {
+            self.orbit = Some(Orbit::new(steps, c, iter_count));
+        }
+        let orbit = self.orbit.as_ref().unwrap();
+
+        // The orbit lives in the same plane as c
+        let screen: Vec<V2> = orbit
+            .points
+            .iter()
+            .take(MAX_DRAWN_POINTS)
+            .map(|z| viewport.world_to_screen_exact(*z))
+            .collect();
+        for w in screen.windows(2) {
+            if w[0].x.is_finite() && w[1].x.is_finite() {
+                state.gpu.line(w[0], w[1], LINE_WIDTH, &self.line);
+            }
+        }
+        for p in screen.iter().filter(|p| p.x.is_finite()) {
+            let size = V2::new(MARKER_SIZE, MARKER_SIZE);
+            state.gpu.blit(&Rect::center_size(*p, size), &self.marker);
+        }
+
+        let text = describe(orbit, viewport.pixel_size());
+        let font_scale = rusttype::Scale::uniform(TEXT_SIZE);
+        let bounds = state.asset.text_bounds(FontType::Mono, font_scale, &text);
+
+        // Next to the cursor, but flipped to the other side near the edges of the screen
+        let size = bounds.size() + V2::new(16.0, 16.0);
+        let res = viewport.size_in_pixels;
+        let mut corner = mouse + V2::new(PANEL_OFFSET, PANEL_OFFSET);
+        if corner.x + size.x > res.x {
+            corner.x = mouse.x - PANEL_OFFSET - size.x;
+        }
+        if corner.y + size.y > res.y {
+            corner.y = mouse.y - PANEL_OFFSET - size.y;
+        }
+
+        let panel = Rect::corner_size(corner, size);
+        let background = state.asset.image("window_back.png");
+        state.gpu.blit(&panel, &background);
+        state.asset.text(
+            FontType::Mono,
+            (corner + V2::new(8.0, 8.0)).map(|x| x as _),
+            V2::new(TextAlignment::Left, TextAlignment::Left),
+            TEXT_SIZE,
+            &mut state.gpu,
+            &text,
+        );
+    }
+}
+
+/// The information shown in the panel, the coordinates have enough digits to tell pixels apart
+fn describe(orbit: &Orbit, pixel_size: f64) -> String {
+    let mut text = format!("c    {}\n", format_complex(orbit.c, pixel_size));
+    text += &format!("iter {}\n", orbit.iterations());
+    text += &match orbit.end {
+        OrbitEnd::Escaped { smooth, z_abs } => {
+            format!("escaped at {:.3}, |z| {:.2}", smooth, z_abs)
+        }
+        OrbitEnd::Periodic { period } => format!("period {}", period),
+        OrbitEnd::Unknown => "no escape or cycle found".to_string(),
+    };
+    text
+}
+
+/// A small image of a single color, to draw lines with
+fn solid_image(color: [u8; 4]) -> Image {
+    let size = 4;
+    Image::new(V2::new(size, size), color.repeat((size * size) as usize))
+}
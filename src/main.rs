@@ -2,20 +2,41 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
+use structopt::StructOpt;
+
 use crate::fractal::Fractal;
+use crate::settings::Args;
+use crate::settings::Settings;
 use crate::state::State;
 use crate::update_loop::Loop;
 
 mod asset_loader;
+mod bookmarks;
 mod builder;
+mod camera_path;
+mod color;
 mod debug;
+mod disk_cache;
+mod domain;
+mod export;
+mod finder;
+mod flight;
 mod fractal;
 mod glyph_cache;
 mod gpu;
+mod history;
 mod image;
+mod inspector;
+mod minimap;
+mod orbit;
 mod pack;
+mod sampling;
+mod settings;
 mod shelf_pack;
 mod state;
+mod tile_cache;
+mod tile_data;
+mod tile_queue;
 mod tilemap;
 mod ui;
 mod update_loop;
@@ -23,12 +44,21 @@ mod util;
 mod viewport;
 
 pub fn main() {
+    let mut settings = Settings::load();
+    settings.apply(&Args::from_args());
+
     let update_loop = Loop::new("Fractal Toy!");
 
     let mut state = State::init(&update_loop.window);
-    let mut fractal = Fractal::init(&mut state);
+    let mut fractal = Fractal::init(&mut state, settings);
     update_loop.run(move |window, input| {
         fractal.update(&mut state, window, input);
-        state.update(window, input);
+
+        // Typing a name should not also toggle the debug overlay
+        if fractal.is_typing() {
+            state.update(window, &input.without_keys());
+        } else {
+            state.update(window, input);
+        }
     });
 }
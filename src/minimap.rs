@@ -0,0 +1,157 @@
+use crate::builder::TileBuilder;
+use crate::color::Coloring;
+use crate::domain::Domain;
+use crate::image::Image;
+use crate::state::State;
+use crate::tilemap::TilePos;
+use crate::ui::UI;
+use crate::update_loop::Input;
+use crate::util::*;
+use crate::viewport::Location;
+use crate::viewport::Viewport;
+
+/// Size of the minimap on the screen, in pixels
+const SIZE: f64 = 240.0;
+
+/// Distance to the right and bottom edges of the screen
+const MARGIN: f64 = 20.0;
+
+/// The viewport marker is never smaller than this, otherwise it disappears when zoomed in
+const MIN_MARKER_SIZE: f64 = 8.0;
+
+/// Build the minimap tiles after the coarse tiles of the view
+const PRIORITY: f64 = 1.0;
+
+/// Overview of the entire domain, with a rectangle where the viewport is.
+///
+/// It uses the tiles at zoom level 0, those are cheap and shared with the view.
+/// Clicking jumps to that position, dragging pans the view.
+pub struct Minimap {
+    pub visible: bool,
+
+    /// Drawn below the tiles, so the tiles that are not built yet are black
+    background: Image,
+}
+
+impl Minimap {
+    pub fn new() -> Self {
+        let size = 256;
+        Minimap {
+            visible: true,
+            background: Image::new(V2::new(size, size), vec![0; (size * size * 4) as usize]),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        state: &mut State,
+        builder: &mut TileBuilder,
+        coloring: &Coloring,
+        viewport: &mut Viewport,
+        input: &Input,
+    ) {
+        if !self.visible {
+            return;
+        }
+
+        let res = viewport.size_in_pixels;
+        let corner = V2::new(res.x - SIZE - MARGIN, res.y - SIZE - MARGIN);
+        let map = Map {
+            rect: Rect::corner_size(corner, V2::new(SIZE, SIZE)),
+            world: world_rect(&viewport.domain),
+        };
+
+        let tiles = map.tiles();
+        for p in tiles.iter() {
+            builder.request(p, PRIORITY);
+        }
+
+        let background = map.quad(&map.world);
+        state.gpu.tile_quad(&background, &self.background, [1.0; 4]);
+        for p in tiles.iter() {
+            if let Some(tile) = builder.tile(p) {
+                let img = coloring.image(tile);
+                let quad = map.quad(&p.square());
+                state.gpu.tile_quad(&quad, &img, [1.0; 4]);
+            }
+        }
+
+        // The bounding box of the view, the view itself can be rotated
+        let size = viewport.size_in_pixels_i.map(|x| x as i32);
+        let corners = [V2::new(0, 0), V2::new(size.x, 0), V2::new(0, size.y), size]
+            .map(|p| map.to_screen(viewport.screen_to_world(p)));
+        let mut min = corners[0];
+        let mut max = corners[0];
+        for c in corners {
+            min = V2::new(min.x.min(c.x), min.y.min(c.y));
+            max = V2::new(max.x.max(c.x), max.y.max(c.y));
+        }
+        let center = (min + max) * 0.5;
+        let marker_size = (max - min).map(|x| x.max(MIN_MARKER_SIZE));
+        let marker = Rect::center_size(center, marker_size);
+
+        UI::outline(&mut state.gpu, &mut state.asset, &map.rect);
+        UI::outline(&mut state.gpu, &mut state.asset, &marker);
+
+        let region = state.ui.region(&map.rect);
+        if region.down {
+            let offset = map.to_world(input.mouse.map(|x| x as f64));
+            viewport.set_location(Location {
+                offset,
+                ..viewport.location()
+            });
+        }
+    }
+}
+
+/// The part of the world that is shown, all tiles of zoom level 0 that overlap the domain
+fn world_rect(domain: &Domain) -> Rect {
+    let min = domain.bounds.corner_min().map(|x| x.floor());
+    let max = domain.bounds.corner_max().map(|x| x.ceil());
+
+    // Keep it square, so the tiles are square too
+    let size = (max - min).x.max((max - min).y);
+    Rect::center_size((min + max) * 0.5, V2::new(size, size))
+}
+
+/// A mapping between the world and the minimap on the screen
+struct Map {
+    rect: Rect,
+    world: Rect,
+}
+
+impl Map {
+    fn tiles(&self) -> Vec<TilePos> {
+        let min = self.world.corner_min();
+        let max = self.world.corner_max();
+        let mut tiles = Vec::new();
+        for y in min.y.floor() as i64..max.y.ceil() as i64 {
+            for x in min.x.floor() as i64..max.x.ceil() as i64 {
+                tiles.push(TilePos { x, y, z: 0 });
+            }
+        }
+        tiles
+    }
+
+    fn to_screen(&self, p: V2) -> V2 {
+        let t = (p - self.world.corner_min()) / self.world.size().x;
+
+        // The screen y axis points down
+        let min = self.rect.corner_min();
+        V2::new(min.x + t.x * SIZE, min.y + (1.0 - t.y) * SIZE)
+    }
+
+    fn to_world(&self, p: V2) -> V2 {
+        let min = self.rect.corner_min();
+        let t = V2::new((p.x - min.x) / SIZE, 1.0 - (p.y - min.y) / SIZE);
+        self.world.corner_min() + t * self.world.size().x
+    }
+
+    /// The corners of a world rectangle on the minimap
+    fn quad(&self, r: &Rect) -> [V2<f32>; 4] {
+        let min = r.corner_min();
+        let max = r.corner_max();
+        [min, V2::new(max.x, min.y), V2::new(min.x, max.y), max]
+            .map(|p| self.to_screen(p).map(|x| x as f32))
+    }
+}
@@ -0,0 +1,427 @@
+use crate::fractal::FractalStep;
+use crate::util::*;
+
+/// Same escape radius as the tile builders, so the escape time matches the pixel
+const ESCAPE_RADIUS_SQR: f64 = 256.0;
+
+/// Squared distance at which an orbit is considered periodic, also the same as the builders
+const PERIOD_EPSILON: f64 = 1e-24;
+
+/// Newton usually converges in a handful of steps, if it does not it is probably lost
+const MAX_NEWTON_STEPS: usize = 64;
+
+/// Newton stops when a step is this small relative to the point, that is about the precision
+const NEWTON_TOLERANCE: f64 = 1e-14;
+
+/// Relative error of z after Newton converged, for every unit of the derivative dz/dc
+const MISIUREWICZ_TOLERANCE: f64 = 1e-12;
+
+/// Misiurewicz points are repelling, so the error grows along the orbit. When it is larger than
+/// this, comparing two points of the orbit no longer means anything.
+const MAX_ORBIT_ERROR: f64 = 1e-6;
+
+/// How an orbit ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OrbitEnd {
+    /// |z| became larger than the escape radius
+    Escaped {
+        /// Smooth escape time, like `Sample::iter`
+        smooth: f64,
+        z_abs: f64,
+    },
+
+    /// The orbit came back to a point it already visited
+    Periodic { period: u32 },
+
+    /// Neither happened within the iteration limit
+    Unknown,
+}
+
+/// The orbit of a single point, evaluated on the cpu
+///
+/// This is separate from the tile builders, those are optimized for many points at once. This
+/// keeps every point of the orbit, so it can be inspected.
+pub struct Orbit {
+    pub c: V2,
+
+    /// Maximum number of iterations, like for the tiles
+    pub iter_count: u32,
+
+    /// z after every iteration, starting with z = 0 before the first one
+    pub points: Vec<V2>,
+
+    pub end: OrbitEnd,
+}
+
+impl Orbit {
+    pub fn new(steps: &[FractalStep], c: V2, iter_count: u32) -> Self {
+        let mut z = V2::zero();
+        let mut points = vec![z];
+
+        // Number of times c was added, the escape time is measured in this
+        let mut t = 0.0;
+
+        // If the orbit comes back to this point it is periodic
+        let mut z_ref = z;
+        let mut z_ref_iter = 0;
+
+        let mut end = OrbitEnd::Unknown;
+        for n in 1..iter_count + 1 {
+            z = iterate(steps, z, c);
+            t += steps.iter().filter(|s| **s == FractalStep::AddC).count() as f64;
+            points.push(z);
+
+            let d = z.magnitude2();
+            if d > ESCAPE_RADIUS_SQR {
+                end = OrbitEnd::Escaped {
+                    smooth: t - d.log2().log2() + 4.0,
+                    z_abs: d.sqrt(),
+                };
+                break;
+            }
+
+            if (z - z_ref).magnitude2() < PERIOD_EPSILON {
+                end = OrbitEnd::Periodic {
+                    period: n - z_ref_iter,
+                };
+                break;
+            }
+
+            // Move the reference point further along, every power of two
+            if n.is_power_of_two() {
+                z_ref = z;
+                z_ref_iter = n;
+            }
+        }
+
+        Orbit {
+            c,
+            iter_count,
+            points,
+            end,
+        }
+    }
+
+    /// Number of iterations until the orbit escaped, or a cycle was found
+    pub fn iterations(&self) -> usize {
+        self.points.len() - 1
+    }
+}
+
+/// Find the lowest period of the nuclei inside the square around `center`, with the box period
+/// method. Periods above `iter_count` are not found, like the tiles the search gives up there.
+///
+/// The corners of the square are iterated together, when the polygon they form surrounds the
+/// origin, a nucleus of that period is inside. This is only exact for analytic formulas, but
+/// it is a good guess for the others too.
+pub fn box_period(steps: &[FractalStep], center: V2, radius: f64, iter_count: u32) -> Option<u32> {
+    let corners = [
+        V2::new(-radius, -radius),
+        V2::new(radius, -radius),
+        V2::new(radius, radius),
+        V2::new(-radius, radius),
+    ]
+    .map(|d| center + d);
+
+    let mut z = [V2::zero(); 4];
+    for n in 1..iter_count + 1 {
+        for (z, c) in z.iter_mut().zip(corners) {
+            *z = iterate(steps, *z, c);
+        }
+
+        // The box is too large, it contains points outside the set
+        if z.iter().any(|z| z.magnitude2() > ESCAPE_RADIUS_SQR) {
+            return None;
+        }
+
+        if surrounds_origin(&z) {
+            return Some(n);
+        }
+    }
+
+    None
+}
+
+/// Refine a guess of a nucleus with this period, the point where z = 0 after `period` iterations
+pub fn find_nucleus(steps: &[FractalStep], guess: V2, period: u32) -> Option<V2> {
+    let mut c = guess;
+    for _ in 0..MAX_NEWTON_STEPS {
+        let mut z = V2::zero();
+        let mut dz = V2::zero();
+        for _ in 0..period {
+            (z, dz) = iterate_derivative(steps, z, dz, c, V2::new(1.0, 0.0));
+        }
+
+        let step = cpx_div(z, dz);
+        c -= step;
+        if !(c.x.is_finite() && c.y.is_finite()) {
+            return None;
+        }
+
+        if step.magnitude() <= NEWTON_TOLERANCE * c.magnitude().max(NEWTON_TOLERANCE) {
+            return Some(c);
+        }
+    }
+
+    None
+}
+
+/// Estimated size of the minibrot at this nucleus, relative to the whole set
+///
+/// This is the estimate from "mandelbrot-numerics" by Claude Heiland-Allen, with the derivative
+/// of the formula instead of 2z.
+pub fn minibrot_size(steps: &[FractalStep], nucleus: V2, period: u32) -> f64 {
+    let one = V2::new(1.0, 0.0);
+
+    // Derivative of z along the orbit, and the sum of its inverses
+    let mut l = one;
+    let mut b = one;
+
+    let mut z = V2::zero();
+    for _ in 1..period {
+        z = iterate(steps, z, nucleus);
+        (_, l) = iterate_derivative(steps, z, l, nucleus, V2::zero());
+        b += cpx_div(one, l);
+    }
+
+    1.0 / cpx_mul(b, cpx_mul(l, l)).magnitude()
+}
+
+/// Guesses for the preperiod and period of Misiurewicz points near `c`, the best guesses first
+///
+/// Near a Misiurewicz point the orbit almost repeats after the preperiod, so the pairs where it
+/// comes closest to repeating are good guesses. Both are at most `max`.
+pub fn misiurewicz_guesses(steps: &[FractalStep], c: V2, max: u32) -> Vec<(u32, u32)> {
+    let mut z = V2::zero();
+    let mut points = vec![z];
+    for _ in 0..max * 2 {
+        z = iterate(steps, z, c);
+        if z.magnitude2() > ESCAPE_RADIUS_SQR {
+            break;
+        }
+        points.push(z);
+    }
+
+    let mut guesses = Vec::new();
+    for k in 1..max as usize + 1 {
+        for p in 1..max as usize + 1 {
+            if let Some(z) = points.get(k + p) {
+                let d = (z - points[k]).magnitude();
+                guesses.push((d, k as u32, p as u32));
+            }
+        }
+    }
+
+    guesses.sort_by(|a, b| a.0.total_cmp(&b.0));
+    guesses.into_iter().map(|(_, k, p)| (k, p)).collect()
+}
+
+/// Refine a guess of a Misiurewicz point, where z after `preperiod` + `period` iterations is the
+/// same as after `preperiod` iterations
+pub fn find_misiurewicz(
+    steps: &[FractalStep],
+    guess: V2,
+    preperiod: u32,
+    period: u32,
+) -> Option<V2> {
+    let mut c = guess;
+    for _ in 0..MAX_NEWTON_STEPS {
+        let mut z = V2::zero();
+        let mut dz = V2::zero();
+        let mut start = (z, dz);
+        for n in 0..preperiod + period {
+            if n == preperiod {
+                start = (z, dz);
+            }
+            (z, dz) = iterate_derivative(steps, z, dz, c, V2::new(1.0, 0.0));
+        }
+
+        let step = cpx_div(z - start.0, dz - start.1);
+        c -= step;
+        if !(c.x.is_finite() && c.y.is_finite()) {
+            return None;
+        }
+
+        if step.magnitude() <= NEWTON_TOLERANCE * c.magnitude().max(NEWTON_TOLERANCE) {
+            return Some(c);
+        }
+    }
+
+    None
+}
+
+/// The lowest preperiod and period of a Misiurewicz point, both at most `max`.
+///
+/// None if the orbit does not repeat, or if it ends in an attracting cycle, like at a nucleus.
+pub fn misiurewicz_type(steps: &[FractalStep], c: V2, max: u32) -> Option<(u32, u32)> {
+    let mut z = V2::zero();
+    let mut dz = V2::zero();
+    let mut points = vec![(z, dz)];
+    for _ in 0..max * 2 {
+        (z, dz) = iterate_derivative(steps, z, dz, c, V2::new(1.0, 0.0));
+        points.push((z, dz));
+    }
+
+    // The error in c is amplified by the derivative
+    let tolerance = |(z, dz): (V2, V2)| {
+        MISIUREWICZ_TOLERANCE * (z.magnitude().max(1.0) + dz.magnitude() * c.magnitude())
+    };
+
+    for k in 0..max as usize + 1 {
+        for p in 1..max as usize + 1 {
+            let (a, b) = (points[k], points[k + p]);
+            let error = tolerance(a).max(tolerance(b));
+            if error < MAX_ORBIT_ERROR && (b.0 - a.0).magnitude() <= error {
+                // The cycle of a Misiurewicz point repels, at a nucleus it attracts
+                let (_, multiplier) = (0..p).fold((a.0, V2::new(1.0, 0.0)), |(z, d), _| {
+                    iterate_derivative(steps, z, d, c, V2::zero())
+                });
+                let repelling = multiplier.magnitude() > 1.0;
+                return (k > 0 && repelling).then_some((k as u32, p as u32));
+            }
+        }
+    }
+
+    None
+}
+
+/// A single iteration of the formula
+pub fn iterate(steps: &[FractalStep], mut z: V2, c: V2) -> V2 {
+    for s in steps.iter() {
+        z = match s {
+            FractalStep::Conj => V2::new(z.x, -z.y),
+            FractalStep::AbsR => V2::new(z.x.abs(), z.y),
+            FractalStep::AbsI => V2::new(z.x, -z.y.abs()),
+            FractalStep::Square => V2::new(z.x * z.x - z.y * z.y, 2.0 * z.x * z.y),
+            FractalStep::Cube => V2::new(
+                z.x * z.x * z.x - 3.0 * z.x * z.y * z.y,
+                3.0 * z.x * z.x * z.y - z.y * z.y * z.y,
+            ),
+            FractalStep::AddC => z + c,
+        };
+    }
+    z
+}
+
+/// A single iteration that also updates the derivative `d` of z. `dc` is the derivative of c, so
+/// 1 gives the derivative to c, and 0 the derivative to the start of the iteration.
+///
+/// Like the tile builders, the non analytic steps only flip the sign of the derivative.
+fn iterate_derivative(steps: &[FractalStep], mut z: V2, mut d: V2, c: V2, dc: V2) -> (V2, V2) {
+    for s in steps.iter() {
+        match s {
+            FractalStep::Conj => {
+                z.y = -z.y;
+                d.y = -d.y;
+            }
+            FractalStep::AbsR => {
+                if z.x < 0.0 {
+                    z.x = -z.x;
+                    d.x = -d.x;
+                }
+            }
+            FractalStep::AbsI => {
+                if z.y > 0.0 {
+                    z.y = -z.y;
+                    d.y = -d.y;
+                }
+            }
+            FractalStep::Square => {
+                d = cpx_mul(z, d) * 2.0;
+                z = cpx_mul(z, z);
+            }
+            FractalStep::Cube => {
+                let z2 = cpx_mul(z, z);
+                d = cpx_mul(z2, d) * 3.0;
+                z = cpx_mul(z2, z);
+            }
+            FractalStep::AddC => {
+                z += c;
+                d += dc;
+            }
+        }
+    }
+    (z, d)
+}
+
+/// The polygon crosses the positive real axis an odd number of times
+fn surrounds_origin(polygon: &[V2]) -> bool {
+    let mut inside = false;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a.y > 0.0) != (b.y > 0.0) {
+            // Where the edge crosses the real axis
+            let x = a.x + (b.x - a.x) * (-a.y / (b.y - a.y));
+            if x > 0.0 {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+fn cpx_mul(a: V2, b: V2) -> V2 {
+    V2::new(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x)
+}
+
+fn cpx_div(a: V2, b: V2) -> V2 {
+    let d = b.magnitude2();
+    V2::new((a.x * b.x + a.y * b.y) / d, (a.y * b.x - a.x * b.y) / d)
+}
+
+#[cfg(test)]
+const TEST_MANDELBROT: &[FractalStep] = &[FractalStep::Square, FractalStep::AddC];
+
+#[test]
+fn test_box_period() {
+    let steps = TEST_MANDELBROT;
+    let n = crate::builder::DEFAULT_ITER_COUNT;
+    assert_eq!(box_period(steps, V2::new(-1.75, 0.0), 0.01, n), Some(3));
+    assert_eq!(box_period(steps, V2::new(-1.0, 0.0), 0.01, n), Some(2));
+    assert_eq!(box_period(steps, V2::new(0.0, 0.0), 0.01, n), Some(1));
+
+    // Outside the set
+    assert_eq!(box_period(steps, V2::new(1.0, 1.0), 0.01, n), None);
+
+    // The period is higher than the iterations
+    assert_eq!(box_period(steps, V2::new(-1.75, 0.0), 0.01, 2), None);
+}
+
+#[test]
+fn test_nucleus() {
+    let steps = TEST_MANDELBROT;
+    let nucleus = find_nucleus(steps, V2::new(-1.75, 0.0), 3).unwrap();
+    assert!((nucleus - V2::new(-1.754_877_666_2, 0.0)).magnitude() < 1e-10);
+    let size = minibrot_size(steps, nucleus, 3);
+    assert!((size - 0.019).abs() < 0.001, "{}", size);
+
+    let nucleus = find_nucleus(steps, V2::new(-0.9, 0.1), 2).unwrap();
+    assert!((nucleus - V2::new(-1.0, 0.0)).magnitude() < 1e-14);
+    assert!((minibrot_size(steps, nucleus, 2) - 0.5).abs() < 1e-12);
+}
+
+#[test]
+fn test_misiurewicz() {
+    let steps = TEST_MANDELBROT;
+    let i = V2::new(0.0, 1.0);
+    let guess = i + V2::new(0.01, -0.02);
+
+    // The way the finder searches, the best guess that converges near the point
+    let found = misiurewicz_guesses(steps, guess, 32)
+        .into_iter()
+        .filter_map(|(k, p)| find_misiurewicz(steps, guess, k, p))
+        .find(|c| (c - guess).magnitude() < 0.1)
+        .unwrap();
+    assert!((found - i).magnitude() < 1e-12);
+    assert_eq!(misiurewicz_type(steps, found, 32), Some((2, 2)));
+
+    // The tip of the needle, 0 -> -2 -> 2 -> 2
+    assert_eq!(
+        misiurewicz_type(steps, V2::new(-2.0, 0.0), 32),
+        Some((2, 1))
+    );
+
+    // A nucleus is not a Misiurewicz point, and a point outside the set neither
+    assert_eq!(misiurewicz_type(steps, V2::new(-1.0, 0.0), 32), None);
+    assert_eq!(misiurewicz_type(steps, V2::new(0.3, 0.0), 32), None);
+}
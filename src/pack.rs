@@ -88,12 +88,12 @@ impl Pack {
 
     pub fn dbg(&self) {
         let pitch = self.size as usize;
-        let mut img = vec![' ' as u8; pitch * pitch];
+        let mut img = vec![b' '; pitch * pitch];
 
         for b in self.free.iter() {
             for y in (b.pos.y)..(b.pos.y + b.size - 1) {
                 for x in (b.pos.x)..(b.pos.x + b.size - 1) {
-                    img[y as usize * pitch + x as usize] = '#' as u8;
+                    img[y as usize * pitch + x as usize] = b'#';
                 }
             }
         }
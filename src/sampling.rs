@@ -0,0 +1,202 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::tile_data::Sample;
+use crate::tile_data::TileData;
+use crate::util::*;
+
+/// Difference in escape time between neighbours above which a pixel is supersampled
+const ADAPTIVE_THRESHOLD: f32 = 0.5;
+
+/// Largest number of samples along one axis of the grid modes
+const MAX_GRID_SIZE: u32 = 8;
+
+/// Sample counts of the jittered mode
+const MIN_JITTER: u32 = 4;
+const MAX_JITTER: u32 = 64;
+
+/// Where inside a pixel the kernel is evaluated
+///
+/// The extra samples are computed in passes, every pass moves the sample position of all pixels
+/// by the same offset. That way the gpu can render a pass just like a normal tile.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum Sampling {
+    /// One sample at the centre of every pixel
+    #[default]
+    Single,
+
+    /// A regular n×n grid of samples in every pixel
+    Grid(u32),
+
+    /// n randomly placed samples in every pixel, the same places in every pixel
+    Jitter(u32),
+
+    /// A regular n×n grid, but only in pixels that differ significantly from their neighbours
+    Adaptive(u32),
+}
+
+/// The extra samples of a tile
+///
+/// Like the samples of the tile these do not depend on the colouring, so the tile is coloured
+/// again without building it again. A pixel gets the average colour of its samples, averaging
+/// the samples themselves would not make sense, half escaped and half interior is not a thing.
+#[derive(Clone)]
+pub struct Supersampled {
+    /// Index of every supersampled pixel
+    pub pixels: Vec<u32>,
+
+    /// The samples of every pass, one for every pixel in the order of `pixels`
+    pub samples: Vec<Sample>,
+}
+
+impl Supersampled {
+    /// The samples of the pixel at index `j` in `pixels`
+    pub fn pixel_samples(&self, j: usize) -> impl Iterator<Item = &Sample> {
+        self.samples.iter().skip(j).step_by(self.pixels.len())
+    }
+}
+
+impl Sampling {
+    pub fn next(self) -> Self {
+        match self {
+            Sampling::Single => Sampling::Adaptive(4),
+            Sampling::Adaptive(_) => Sampling::Grid(4),
+            Sampling::Grid(_) => Sampling::Jitter(16),
+            Sampling::Jitter(_) => Sampling::Single,
+        }
+    }
+
+    /// The same mode with more samples, wrapping around to the fewest
+    pub fn more_samples(self) -> Self {
+        let grid = |n: u32| if n >= MAX_GRID_SIZE { 2 } else { n + 1 };
+        match self {
+            Sampling::Single => Sampling::Single,
+            Sampling::Grid(n) => Sampling::Grid(grid(n)),
+            Sampling::Adaptive(n) => Sampling::Adaptive(grid(n)),
+            Sampling::Jitter(n) if n >= MAX_JITTER => Sampling::Jitter(MIN_JITTER),
+            Sampling::Jitter(n) => Sampling::Jitter(n * 2),
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Sampling::Single => "Single".to_string(),
+            Sampling::Grid(n) => format!("Grid {}x{}", n, n),
+            Sampling::Jitter(n) => format!("Jitter {}", n),
+            Sampling::Adaptive(n) => format!("Adaptive {}x{}", n, n),
+        }
+    }
+
+    /// The pixels in this tile that should be supersampled
+    pub fn pixels(self, data: &TileData) -> Vec<u32> {
+        let count = data.size.x * data.size.y;
+        match self {
+            Sampling::Single => Vec::new(),
+            Sampling::Grid(_) | Sampling::Jitter(_) => (0..count).collect(),
+            Sampling::Adaptive(_) => (0..count).filter(|i| needs_refinement(data, *i)).collect(),
+        }
+    }
+
+    /// Sample position inside the pixels for every pass, from 0 to 1
+    pub fn passes(self) -> Vec<V2> {
+        match self {
+            Sampling::Single => Vec::new(),
+            Sampling::Grid(n) | Sampling::Adaptive(n) => {
+                let step = 1.0 / n as f64;
+                (0..n * n)
+                    .map(|j| V2::new((j % n) as f64 + 0.5, (j / n) as f64 + 0.5) * step)
+                    .collect()
+            }
+
+            // The positions are always the same, so a tile always looks the same
+            Sampling::Jitter(n) => (0..n)
+                .map(|j| {
+                    let h = hash(j + 1);
+                    let x = (h & 0xffff) as f64 / 65536.0;
+                    let y = (h >> 16) as f64 / 65536.0;
+                    V2::new(x, y)
+                })
+                .collect(),
+        }
+    }
+
+    /// Supersample a built tile
+    ///
+    /// `pass` computes the samples of the given pixels, with the sample position moved to the
+    /// given place inside the pixel. Returns None if `pass` does, when building was aborted.
+    pub fn supersample(
+        self,
+        data: &TileData,
+        mut pass: impl FnMut(&[u32], V2) -> Option<Vec<Sample>>,
+    ) -> Option<Supersampled> {
+        let pixels = self.pixels(data);
+        let passes = self.passes();
+
+        let mut samples = Vec::with_capacity(pixels.len() * passes.len());
+        if !pixels.is_empty() {
+            for offset in passes.iter() {
+                samples.extend(pass(&pixels, *offset)?);
+            }
+        }
+
+        Some(Supersampled { pixels, samples })
+    }
+}
+
+/// A pixel is refined if it is on a thin filament, or if it differs from one of its neighbours
+fn needs_refinement(data: &TileData, i: u32) -> bool {
+    let w = data.size.x;
+    let h = data.size.y;
+    let x = i % w;
+    let y = i / w;
+
+    let s = &data.samples[i as usize];
+    if s.escaped() && s.de < 1.0 {
+        return true;
+    }
+
+    let differs = |o: &Sample| {
+        o.escaped() != s.escaped() || (o.escaped() && (o.iter - s.iter).abs() > ADAPTIVE_THRESHOLD)
+    };
+
+    (x > 0 && differs(&data.samples[i as usize - 1]))
+        || (x + 1 < w && differs(&data.samples[i as usize + 1]))
+        || (y > 0 && differs(&data.samples[(i - w) as usize]))
+        || (y + 1 < h && differs(&data.samples[(i + w) as usize]))
+}
+
+/// Integer hash, good enough to place samples
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+#[test]
+fn test_supersample() {
+    let samples = vec![bytemuck::Zeroable::zeroed(); 4];
+    let data = TileData::new(V2::new(2, 2), samples);
+
+    // Every pass stores the pass number in the samples of all pixels
+    let mut n = 0.0;
+    let sampling = Sampling::Grid(2);
+    let supersampled = sampling.supersample(&data, |pixels, _| {
+        n += 1.0;
+        let mut s: Sample = bytemuck::Zeroable::zeroed();
+        s.iter = n;
+        Some(vec![s; pixels.len()])
+    });
+
+    let supersampled = supersampled.unwrap();
+    assert_eq!(supersampled.pixels, vec![0, 1, 2, 3]);
+    for j in 0..4 {
+        let iters = supersampled.pixel_samples(j).map(|s| s.iter);
+        assert_eq!(iters.collect::<Vec<_>>(), vec![1.0, 2.0, 3.0, 4.0]);
+    }
+
+    // Aborted
+    assert!(sampling.supersample(&data, |_, _| None).is_none());
+}
@@ -0,0 +1,107 @@
+use serde::Deserialize;
+use serde::Serialize;
+use structopt::StructOpt;
+
+/// Machine specific settings, unlike the saved state these are not shared between machines
+const SETTINGS_DIR: &str = ".fractal-toy";
+const SETTINGS_FILE: &str = ".fractal-toy/settings.ron";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerConfig {
+    /// Number of cpu workers, None uses all cores but one
+    pub cpu_workers: Option<usize>,
+
+    /// Number of tiles that are built on the gpu at the same time
+    pub gpu_jobs: usize,
+
+    /// Run the workers at a lower priority, so the rest of the system stays responsive
+    pub background: bool,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            cpu_workers: None,
+            gpu_jobs: 1,
+            background: false,
+        }
+    }
+}
+
+impl WorkerConfig {
+    pub fn cpu_workers(&self) -> usize {
+        match self.cpu_workers {
+            Some(n) => n.max(1),
+            None => (num_cpus::get() - 1).max(1),
+        }
+    }
+
+    pub fn gpu_jobs(&self) -> usize {
+        self.gpu_jobs.max(1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub workers: WorkerConfig,
+
+    /// Store finished cpu tiles on disk, and load them from there
+    #[serde(default)]
+    pub disk_cache: bool,
+}
+
+impl Settings {
+    /// Load the settings file, falling back to the defaults if there is none
+    pub fn load() -> Self {
+        let data = match std::fs::read_to_string(SETTINGS_FILE) {
+            Ok(data) => data,
+            Err(_) => return Settings::default(),
+        };
+
+        match ron::from_str(&data) {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", SETTINGS_FILE, e);
+                Settings::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let data = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).unwrap();
+        std::fs::create_dir_all(SETTINGS_DIR)?;
+        std::fs::write(SETTINGS_FILE, data)
+    }
+
+    /// The command line overrides the settings file
+    pub fn apply(&mut self, args: &Args) {
+        if let Some(n) = args.workers {
+            self.workers.cpu_workers = Some(n);
+        }
+
+        if let Some(n) = args.gpu_jobs {
+            self.workers.gpu_jobs = n;
+        }
+
+        if args.background {
+            self.workers.background = true;
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "fractal-toy")]
+pub struct Args {
+    /// Number of cpu workers, defaults to all cores but one
+    #[structopt(long)]
+    pub workers: Option<usize>,
+
+    /// Number of tiles built on the gpu at the same time
+    #[structopt(long)]
+    pub gpu_jobs: Option<usize>,
+
+    /// Run the workers at a lower priority
+    #[structopt(long)]
+    pub background: bool,
+}
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+
+use crate::builder::Tile;
+use crate::tilemap::TilePos;
+
+/// Default memory budget of the tile cache, in bytes
+pub const DEFAULT_CACHE_BUDGET: usize = 1024 * 1024 * 1024;
+
+/// Finished tiles, and tiles that are still being built
+///
+/// The cache is bounded by a memory budget, when it is exceeded the least recently used tiles are
+/// evicted. Tiles used in the current frame are pinned, they are never evicted, so the budget
+/// can be exceeded when a lot of tiles are visible.
+pub struct TileCache {
+    tiles: BTreeMap<TilePos, Slot>,
+
+    /// Incremented every update, used as the timestamp for the LRU eviction
+    frame: u64,
+
+    /// Maximum number of bytes used by the cached tiles
+    pub budget: usize,
+
+    pub stats: CacheStats,
+}
+
+struct Slot {
+    /// None if the tile is still being built
+    tile: Option<Tile>,
+    last_used: u64,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct CacheStats {
+    /// Tiles that came back into view and were already built, tiles that stay in view are only
+    /// counted once
+    pub hits: u64,
+
+    /// Requested tiles that had to be built
+    pub misses: u64,
+
+    /// Tiles removed to stay within the budget
+    pub evictions: u64,
+
+    pub bytes: usize,
+    pub tiles: usize,
+}
+
+impl TileCache {
+    pub fn new(budget: usize) -> Self {
+        TileCache {
+            tiles: BTreeMap::new(),
+            frame: 0,
+            budget,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Return a built tile, and pin it for this frame
+    pub fn get(&mut self, p: &TilePos) -> Option<&mut Tile> {
+        let slot = self.tiles.get_mut(p)?;
+        let tile = slot.tile.as_mut()?;
+        slot.last_used = self.frame;
+        Some(tile)
+    }
+
+    /// Is this tile built, does not count as a use
+    pub fn contains(&self, p: &TilePos) -> bool {
+        self.tiles.get(p).is_some_and(|s| s.tile.is_some())
+    }
+
+    /// Mark a tile as requested, returns true if it has to be queued
+    pub fn request(&mut self, p: &TilePos) -> bool {
+        match self.tiles.get_mut(p) {
+            Some(slot) => {
+                // Not used in the previous frame, so it was out of view
+                let returned = slot.last_used + 1 < self.frame;
+                slot.last_used = self.frame;
+                match slot.tile {
+                    Some(_) => {
+                        if returned {
+                            self.stats.hits += 1;
+                        }
+                        false
+                    }
+
+                    // already queued, but it might need a new priority
+                    None => true,
+                }
+            }
+            None => {
+                self.stats.misses += 1;
+                self.tiles.insert(
+                    *p,
+                    Slot {
+                        tile: None,
+                        last_used: self.frame,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Tiles that are queued or being built
+    pub fn pending(&self) -> Vec<TilePos> {
+        self.tiles
+            .iter()
+            .filter(|(_, s)| s.tile.is_none())
+            .map(|(p, _)| *p)
+            .collect()
+    }
+
+    pub fn insert(&mut self, p: TilePos, tile: Tile) {
+        self.tiles.insert(
+            p,
+            Slot {
+                tile: Some(tile),
+                last_used: self.frame,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, p: &TilePos) {
+        self.tiles.remove(p);
+    }
+
+    /// Evict tiles until we are within the budget again, and start a new frame
+    pub fn update(&mut self) {
+        let mut bytes = self
+            .tiles
+            .values()
+            .filter_map(|s| s.tile.as_ref())
+            .map(Tile::bytes)
+            .sum();
+
+        if bytes > self.budget {
+            // Unpinned and finished tiles, least recently used first
+            let mut candidates = self
+                .tiles
+                .iter()
+                .filter(|(_, s)| s.last_used < self.frame)
+                .filter_map(|(p, s)| Some((s.last_used, *p, s.tile.as_ref()?)))
+                .filter(|(_, _, t)| t.data.is_complete())
+                .map(|(used, p, t)| (used, p, t.bytes()))
+                .collect::<Vec<_>>();
+            candidates.sort();
+
+            for (_, p, size) in candidates {
+                if bytes <= self.budget {
+                    break;
+                }
+
+                self.tiles.remove(&p);
+                self.stats.evictions += 1;
+                bytes -= size;
+            }
+        }
+
+        self.stats.bytes = bytes;
+        self.stats.tiles = self.tiles.values().filter(|s| s.tile.is_some()).count();
+        self.frame += 1;
+    }
+}
+
+#[cfg(test)]
+fn test_tile() -> Tile {
+    use crate::tile_data::TileData;
+    use crate::util::V2;
+
+    let samples = vec![bytemuck::Zeroable::zeroed(); 4];
+    Tile {
+        data: TileData::new(V2::new(2, 2), samples),
+        image: None,
+        received: std::time::Instant::now(),
+    }
+}
+
+#[test]
+fn test_eviction_order() {
+    let p = |x| TilePos { x, y: 0, z: 1 };
+    let mut cache = TileCache::new(test_tile().bytes() * 2);
+
+    for x in 0..3 {
+        cache.insert(p(x), test_tile());
+        cache.update();
+    }
+
+    // Over budget by one tile, the least recently used goes first
+    assert!(!cache.contains(&p(0)));
+    assert!(cache.contains(&p(1)));
+    assert!(cache.contains(&p(2)));
+    assert_eq!(cache.stats.evictions, 1);
+
+    // Using a tile makes it the most recent
+    cache.get(&p(1));
+    cache.update();
+    cache.insert(p(3), test_tile());
+    cache.update();
+    assert!(cache.contains(&p(1)));
+    assert!(!cache.contains(&p(2)));
+    assert!(cache.contains(&p(3)));
+}
+
+#[test]
+fn test_pinned() {
+    let p = |x| TilePos { x, y: 0, z: 1 };
+    let mut cache = TileCache::new(0);
+
+    // Tiles used in this frame are kept, even over budget
+    cache.insert(p(0), test_tile());
+    cache.insert(p(1), test_tile());
+    cache.update();
+    assert!(cache.contains(&p(0)));
+    assert!(cache.contains(&p(1)));
+
+    cache.get(&p(1));
+    cache.update();
+    assert!(!cache.contains(&p(0)));
+    assert!(cache.contains(&p(1)));
+    assert_eq!(cache.stats.bytes, test_tile().bytes());
+}
+
+#[test]
+fn test_hits() {
+    let p = TilePos { x: 0, y: 0, z: 1 };
+    let mut cache = TileCache::new(usize::MAX);
+
+    assert!(cache.request(&p));
+    cache.insert(p, test_tile());
+    for _ in 0..4 {
+        cache.update();
+        assert!(!cache.request(&p));
+    }
+    assert_eq!(cache.stats.misses, 1);
+    assert_eq!(cache.stats.hits, 0);
+
+    // Out of view for a frame, and then back
+    cache.update();
+    cache.update();
+    assert!(!cache.request(&p));
+    assert_eq!(cache.stats.hits, 1);
+}
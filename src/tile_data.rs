@@ -0,0 +1,104 @@
+use crate::sampling::Supersampled;
+use crate::util::*;
+
+/// Number of bins in the per-tile escape time histogram
+pub const HISTOGRAM_BINS: usize = 1024;
+
+/// Everything a kernel computes for a single point.
+///
+/// This does not depend on the colouring, that is applied later when the tile is drawn.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Sample {
+    /// Smooth escape time, negative if the point never escaped
+    pub iter: f32,
+
+    /// Estimated distance to the boundary of the set, in pixels.
+    ///
+    /// Computed from the derivative dz/dc. Below one pixel the point is close to a thin
+    /// filament, which a single sample per pixel does not resolve.
+    ///
+    /// Points inside the set get the interior distance estimate, if a period was found.
+    pub de: f32,
+
+    /// Direction of the surface normal in radians, the angle of z/(dz/dc).
+    ///
+    /// This is the gradient direction of the escape potential, so it can be used for lighting
+    /// without looking at neighbouring pixels or tiles.
+    pub normal: f32,
+
+    /// Closest distance of the orbit to the orbit trap, also set for points that never escaped
+    pub trap_dist: f32,
+
+    /// Escape time at which the orbit came closest to the trap
+    pub trap_iter: f32,
+
+    /// |z| after the last iteration
+    pub z_abs: f32,
+
+    /// Period of the attracting cycle, zero if none was detected
+    pub period: f32,
+
+    /// Atom domain, the iteration at which |z| was the smallest
+    pub atom: f32,
+}
+
+unsafe impl bytemuck::Pod for Sample {}
+unsafe impl bytemuck::Zeroable for Sample {}
+
+impl Sample {
+    pub fn escaped(&self) -> bool {
+        self.iter >= 0.0
+    }
+}
+
+/// The raw result of building a tile
+#[derive(Clone)]
+pub struct TileData {
+    pub size: V2<u32>,
+
+    /// One sample per pixel, row by row
+    pub samples: Vec<Sample>,
+
+    /// Extra samples of some pixels, those pixels get the average colour of their samples
+    pub supersampled: Option<Supersampled>,
+
+    /// Distance between the pixels that were actually computed, the others are copies.
+    /// Tiles can be delivered in coarse passes first, a tile is complete when this is 1.
+    pub step: u32,
+
+    /// Escape time histogram of this tile, see `histogram_bin`
+    pub histogram: Vec<u32>,
+}
+
+impl TileData {
+    pub fn new(size: V2<u32>, samples: Vec<Sample>) -> Self {
+        assert_eq!(samples.len(), size.x as usize * size.y as usize);
+
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+        for s in samples.iter().filter(|s| s.escaped()) {
+            histogram[histogram_bin(s.iter) as usize] += 1;
+        }
+
+        TileData {
+            size,
+            samples,
+            supersampled: None,
+            step: 1,
+            histogram,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.step == 1
+    }
+}
+
+/// Fractional histogram bin for an escape time.
+///
+/// The bins are logarithmic, most of the interesting detail lives in the low escape times,
+/// but deep zooms can easily reach thousands of iterations.
+pub fn histogram_bin(iter: f32) -> f32 {
+    let bin = (1.0 + iter.max(0.0)).log2() * (HISTOGRAM_BINS as f32 / 16.0);
+    bin.min(HISTOGRAM_BINS as f32 - 1.0)
+}
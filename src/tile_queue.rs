@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::sync::Condvar;
+use std::sync::Mutex;
+
+use crate::tilemap::TilePos;
+
+/// Tiles waiting to be built, shared between the builder and its workers.
+///
+/// Unlike a channel, queued tiles can be reprioritized or cancelled when the viewport moves,
+/// so the workers never waste time on tiles that are no longer visible.
+pub struct TileQueue {
+    state: Mutex<QueueState>,
+    wake: Condvar,
+}
+
+struct QueueState {
+    /// Queued tiles with their priority, lower is built first
+    jobs: BTreeMap<TilePos, f64>,
+
+    /// Tiles that a worker is working on, these are not queued again
+    building: BTreeSet<TilePos>,
+
+    /// No more jobs will be pushed, the workers should stop
+    closed: bool,
+}
+
+impl TileQueue {
+    pub fn new() -> Self {
+        TileQueue {
+            state: Mutex::new(QueueState {
+                jobs: BTreeMap::new(),
+                building: BTreeSet::new(),
+                closed: false,
+            }),
+            wake: Condvar::new(),
+        }
+    }
+
+    /// Queue a tile, or update its priority if it is already queued
+    pub fn push(&self, p: TilePos, priority: f64) {
+        let mut state = self.state.lock().unwrap();
+        if state.building.contains(&p) {
+            return;
+        }
+
+        if state.jobs.insert(p, priority).is_none() {
+            self.wake.notify_one();
+        }
+    }
+
+    /// Remove a tile from the queue, returns false if it was not queued (anymore)
+    pub fn cancel(&self, p: &TilePos) -> bool {
+        self.state.lock().unwrap().jobs.remove(p).is_some()
+    }
+
+    /// Wait for the most important tile, returns None when the queue is closed.
+    ///
+    /// Call `finish` when the tile is built.
+    pub fn pop(&self) -> Option<TilePos> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+
+            let best = state
+                .jobs
+                .iter()
+                .min_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(p, _)| *p);
+
+            if let Some(p) = best {
+                state.jobs.remove(&p);
+                state.building.insert(p);
+                return Some(p);
+            }
+
+            state = self.wake.wait(state).unwrap();
+        }
+    }
+
+    pub fn finish(&self, p: &TilePos) {
+        self.state.lock().unwrap().building.remove(p);
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().jobs.len()
+    }
+
+    /// Drop all queued tiles and stop the workers once they finish their current tile
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.jobs.clear();
+        self.wake.notify_all();
+    }
+}
+
+#[test]
+fn test_priority() {
+    let q = TileQueue::new();
+    let p = |x| TilePos { x, y: 0, z: 1 };
+    q.push(p(0), 2.0);
+    q.push(p(1), 1.0);
+    q.push(p(2), 3.0);
+
+    // Pushing again updates the priority
+    q.push(p(2), 0.5);
+    assert_eq!(q.len(), 3);
+
+    assert_eq!(q.pop(), Some(p(2)));
+    assert_eq!(q.pop(), Some(p(1)));
+    assert_eq!(q.pop(), Some(p(0)));
+    assert_eq!(q.len(), 0);
+}
+
+#[test]
+fn test_cancel() {
+    let q = TileQueue::new();
+    let p = |x| TilePos { x, y: 0, z: 1 };
+    q.push(p(0), 1.0);
+    q.push(p(1), 2.0);
+
+    assert!(q.cancel(&p(0)));
+    assert!(!q.cancel(&p(0)));
+    assert_eq!(q.pop(), Some(p(1)));
+
+    // A tile that is being built can not be cancelled
+    assert!(!q.cancel(&p(1)));
+}
+
+#[test]
+fn test_building() {
+    let q = TileQueue::new();
+    let p = TilePos { x: 0, y: 0, z: 1 };
+    q.push(p, 1.0);
+    assert_eq!(q.pop(), Some(p));
+
+    // Not queued again while a worker is building it
+    q.push(p, 1.0);
+    assert_eq!(q.len(), 0);
+
+    q.finish(&p);
+    q.push(p, 1.0);
+    assert_eq!(q.pop(), Some(p));
+
+    q.close();
+    assert_eq!(q.pop(), None);
+}
@@ -14,14 +14,16 @@ impl TilePos {
         TilePos { x: 0, y: 0, z: 0 }
     }
 
+    /// The tile one level up that contains this tile
     pub fn parent(&self) -> Option<TilePos> {
         if self.z == 0 {
             return None;
         }
 
+        // Rounded down, -1 is in the tile left of 0
         Some(TilePos {
-            x: self.x / 2,
-            y: self.y / 2,
+            x: self.x.div_euclid(2),
+            y: self.y.div_euclid(2),
             z: self.z - 1,
         })
     }
@@ -96,3 +98,20 @@ fn test_fromf64() {
     assert_eq!(TilePos::at(0.4, 0.5, 1), TilePos { x: 0, y: 1, z: 1 });
     assert_eq!(TilePos::at(0.0, 0.0, 16), TilePos { x: 0, y: 0, z: 16 });
 }
+
+#[rustfmt::skip]
+#[test]
+fn test_parent() {
+    assert_eq!(TilePos::root().parent(), None);
+    assert_eq!(TilePos { x: 3, y: 2, z: 2 }.parent(), Some(TilePos { x: 1, y: 1, z: 1 }));
+    assert_eq!(TilePos { x: -1, y: -1, z: 1 }.parent(), Some(TilePos { x: -1, y: -1, z: 0 }));
+    assert_eq!(TilePos { x: -2, y: -3, z: 2 }.parent(), Some(TilePos { x: -1, y: -2, z: 1 }));
+
+    // The parent contains the tile, also left of the origin
+    for x in -4..4 {
+        let p = TilePos { x, y: 0, z: 3 };
+        let parent = p.parent().unwrap().square();
+        let center = p.square().center();
+        assert!(parent.corner_min().x <= center.x && center.x <= parent.corner_max().x);
+    }
+}
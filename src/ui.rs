@@ -44,24 +44,39 @@ impl Window {
         Rect::corner_size(self.position, self.bounds.size())
     }
 
+    /// Where a rectangle in the window ends up on the screen, to find out if it is clicked
+    pub fn to_screen(&self, rect: &Rect) -> Rect {
+        let mut rect = *rect;
+        rect.translate(self.position - self.bounds.corner_min());
+        rect
+    }
+
     pub fn unuse(&mut self) {
         self.used = false;
     }
 
+    /// Make the window at least this large
+    pub fn reserve(&mut self, rect: Rect) {
+        self.used = true;
+        self.bounds.extend(&rect);
+    }
+
     pub fn image(&mut self, rect: Rect, image: Image) {
         self.used = true;
         self.bounds.extend(&rect);
         self.images.push((rect, image));
     }
 
-    pub fn text(&mut self, asset_loader: &mut AssetLoader, kind: FontType, text: &str) {
-        let itr = asset_loader.text_iter(
-            kind,
-            V2::zero(),
-            V2::new(TextAlignment::Left, TextAlignment::Left),
-            26.0,
-            text,
-        );
+    pub fn text(
+        &mut self,
+        asset_loader: &mut AssetLoader,
+        kind: FontType,
+        p: V2<i32>,
+        align: V2<TextAlignment>,
+        size: f32,
+        text: &str,
+    ) {
+        let itr = asset_loader.text_iter(kind, p, align, size, text);
 
         for (rect, img) in itr {
             self.image(rect, img);
@@ -83,9 +98,35 @@ impl Window {
         }
     }
 
-    pub fn button(&mut self, img: Image) -> bool {
-        self.image(Rect::center_size(V2::zero(), V2::new(60.0, 60.0)), img);
-        false
+    /// A button with a text label, returns true when it is clicked
+    pub fn button(
+        &mut self,
+        ui: &mut UI,
+        asset: &mut AssetLoader,
+        rect: Rect,
+        text: &str,
+        text_size: f32,
+    ) -> bool {
+        let region = ui.region(&self.to_screen(&rect));
+        self.image(rect, asset.image("button_back.png"));
+        self.text(
+            asset,
+            FontType::Normal,
+            rect.center().map(|x| x as _),
+            V2::new(TextAlignment::Center, TextAlignment::Center),
+            text_size,
+            text,
+        );
+
+        let image_front = asset.image(if region.down {
+            "button_front_down.png"
+        } else if region.hover {
+            "button_front_hot.png"
+        } else {
+            "button_front_norm.png"
+        });
+        self.image(rect, image_front);
+        region.click
     }
 }
 
@@ -108,6 +149,9 @@ pub struct UI {
     hover_prev_frame: Option<u32>,
     down: Option<u32>,
 
+    /// The mouse was above a window in the previous frame
+    window_hover: bool,
+
     button_img: Image,
 }
 
@@ -132,6 +176,7 @@ impl UI {
             hover: None,
             hover_prev_frame: None,
             down: None,
+            window_hover: false,
 
             button_img: asset.image("button_front_hot.png"),
         }
@@ -162,22 +207,86 @@ impl UI {
         RegionResult { hover, down, click }
     }
 
+    /// Horizontal slider, `value` goes from 0 to 1, the new value is returned
+    pub fn slider(
+        &mut self,
+        gpu: &mut Gpu,
+        asset: &mut AssetLoader,
+        rect: &Rect,
+        value: f64,
+    ) -> f64 {
+        let region = self.region(rect);
+
+        let value = if region.down {
+            ((self.mouse.x - rect.min.x) / rect.size().x).clamp(0.0, 1.0)
+        } else {
+            value
+        };
+
+        gpu.blit(rect, &asset.image("button_back.png"));
+
+        let knob_size = rect.size().y;
+        let knob_x = rect.min.x + knob_size * 0.5 + (rect.size().x - knob_size) * value;
+        let knob = Rect::center_size(
+            V2::new(knob_x, rect.center().y),
+            V2::new(knob_size, knob_size),
+        );
+
+        let knob_image = if region.down {
+            "button_front_down.png"
+        } else if region.hover {
+            "button_front_hot.png"
+        } else {
+            "slider.png"
+        };
+        gpu.blit(&knob, &asset.image(knob_image));
+
+        value
+    }
+
+    /// Draw the border of a rectangle
+    pub fn outline(gpu: &mut Gpu, asset: &mut AssetLoader, rect: &Rect) {
+        let w = 2.0;
+        let image = asset.image("window_back.png");
+        let min = rect.corner_min();
+        let max = rect.corner_max();
+        for r in [
+            Rect::min_max(min, V2::new(max.x, min.y + w)),
+            Rect::min_max(V2::new(min.x, max.y - w), max),
+            Rect::min_max(min, V2::new(min.x + w, max.y)),
+            Rect::min_max(V2::new(max.x - w, min.y), max),
+        ] {
+            gpu.blit(&r, &image);
+        }
+    }
+
     pub fn has_input(&self) -> bool {
         let has_hover = self.hover.is_some() || self.hover_prev_frame.is_some();
         let has_down = self.down.is_some() && self.down != Some(0);
-        has_hover || has_down || self.draggin_window.is_some()
+        has_hover || has_down || self.window_hover || self.draggin_window.is_some()
     }
 
     pub fn next_row(&mut self) {}
 
     pub fn next_col(&mut self) {}
 
-    pub fn window(&mut self, name: &'static str) -> &mut Window {
-        let window = self.windows.entry(name).or_insert(Window::new(name));
+    /// Start filling a window, it is shown at `position` the first time, after that it can be
+    /// dragged around. The window is taken out of the ui, so buttons can be added to it, give it
+    /// back with `end_window`. Windows that are not filled in a frame are hidden.
+    pub fn begin_window(&mut self, name: &'static str, position: V2) -> Window {
+        let mut window = self.windows.remove(name).unwrap_or_else(|| {
+            let mut window = Window::new(name);
+            window.position = position;
+            window
+        });
         window.reset();
         window
     }
 
+    pub fn end_window(&mut self, window: Window) {
+        self.windows.insert(window.name, window);
+    }
+
     pub fn update(&mut self, input: &Input, gpu: &mut Gpu, asset: &mut AssetLoader) {
         // end of frame
         if self.mouse_down && self.down.is_none() {
@@ -185,6 +294,9 @@ impl UI {
         }
         self.hover_prev_frame = self.hover.take();
 
+        // Pressing a button in a window should not also drag it
+        let widget_down = self.down.is_some_and(|id| id != 0);
+
         // begin next frame
         self.mouse_down = input.mouse_down;
         self.mouse = input.mouse.map(|x| x as _);
@@ -201,11 +313,17 @@ impl UI {
         }
 
         let mouse_pos = input.mouse.map(|x| x as _);
+        self.window_hover = false;
         for w in self.windows.values_mut() {
+            if !w.used {
+                continue;
+            }
+
             let rect = w.content_rect();
             let hover = rect.contains(mouse_pos);
+            self.window_hover |= hover;
 
-            if hover && input.mouse_down && self.draggin_window.is_none() {
+            if hover && input.mouse_down && self.draggin_window.is_none() && !widget_down {
                 self.draggin_window = Some((w.name, mouse_pos - w.position));
             }
 
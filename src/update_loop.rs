@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use ::instant::Duration;
 use ::instant::Instant;
 use winit::event::ElementState;
@@ -13,7 +15,23 @@ use winit::window::WindowBuilder;
 
 use crate::util::*;
 
-#[derive(Debug)]
+/// Two clicks within this time and distance are a double click
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+const DOUBLE_CLICK_DISTANCE: i32 = 4;
+
+/// The back and forward buttons on the side of a mouse, these are numbered differently on every
+/// platform
+#[cfg(target_os = "windows")]
+const MOUSE_BACK_FORWARD: (u16, u16) = (1, 2);
+#[cfg(target_os = "macos")]
+const MOUSE_BACK_FORWARD: (u16, u16) = (3, 4);
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const MOUSE_BACK_FORWARD: (u16, u16) = (8, 9);
+
+/// Touchpads scroll in pixels instead of lines, this many pixels are one line
+const SCROLL_PIXELS_PER_LINE: f64 = 20.0;
+
+#[derive(Debug, Clone)]
 pub struct Input {
     pub dt: f32,
     pub real_dt_full: Duration,
@@ -23,12 +41,24 @@ pub struct Input {
     pub mouse: V2<i32>,
     pub mouse_down: bool,
     pub mouse_click: bool,
+    pub mouse_double_click: bool,
+    pub mouse_right_down: bool,
+
+    /// The back or forward mouse button was pressed
+    pub mouse_back: bool,
+    pub mouse_forward: bool,
 
     pub mouse_scroll: f32,
 
     // TODO: is there a better way to do this?
     pub keys_down: Vec<VirtualKeyCode>,
     pub keys_click: Vec<VirtualKeyCode>,
+
+    /// Characters typed since the last update
+    pub text: String,
+
+    /// Files dropped on the window since the last update
+    pub dropped_files: Vec<PathBuf>,
 }
 
 impl Input {
@@ -39,6 +69,26 @@ impl Input {
     pub fn key_click(&self, key: VirtualKeyCode) -> bool {
         self.keys_click.contains(&key)
     }
+
+    /// Either shift key is held
+    pub fn shift(&self) -> bool {
+        self.key(VirtualKeyCode::LShift) || self.key(VirtualKeyCode::RShift)
+    }
+
+    /// Either control key is held
+    pub fn ctrl(&self) -> bool {
+        self.key(VirtualKeyCode::LControl) || self.key(VirtualKeyCode::RControl)
+    }
+
+    /// The same input, but without any keys, for while the keyboard is used to type text
+    pub fn without_keys(&self) -> Input {
+        Input {
+            keys_down: Vec::new(),
+            keys_click: Vec::new(),
+            text: String::new(),
+            ..self.clone()
+        }
+    }
 }
 
 pub struct Loop {
@@ -70,15 +120,24 @@ impl Loop {
             mouse: V2::new(0, 0),
             mouse_down: false,
             mouse_click: false,
+            mouse_double_click: false,
+            mouse_right_down: false,
+            mouse_back: false,
+            mouse_forward: false,
             mouse_scroll: 0.0,
             keys_down: Vec::new(),
             keys_click: Vec::new(),
+            text: String::new(),
+            dropped_files: Vec::new(),
         };
 
         // At what time do we want a new update
         let mut next_frame_time = Instant::now();
         let mut last_frame_time = Instant::now();
 
+        // Time and position of the last click, to detect double clicks
+        let mut last_click: Option<(Instant, V2<i32>)> = None;
+
         // NOTE: we are ignoring redraw requests for now,
         // and are both updating and rendering in MainEventsCleared.
         // This might result into issues in the web platform,
@@ -119,6 +178,16 @@ impl Loop {
                     }
                 },
 
+                Event::WindowEvent {
+                    window_id: _,
+                    event: WindowEvent::ReceivedCharacter(c),
+                } if !c.is_control() => input.text.push(c),
+
+                Event::WindowEvent {
+                    window_id: _,
+                    event: WindowEvent::DroppedFile(path),
+                } => input.dropped_files.push(path),
+
                 // Respect window close button
                 Event::WindowEvent {
                     window_id: _,
@@ -135,16 +204,63 @@ impl Loop {
 
                 Event::WindowEvent {
                     window_id: _,
-                    event: WindowEvent::MouseInput { button, state, .. },
+                    event:
+                        WindowEvent::MouseInput {
+                            button: MouseButton::Left,
+                            state,
+                            ..
+                        },
                 } => {
-                    if button == MouseButton::Left {
-                        let was_down = input.mouse_down;
-                        let is_down = state == ElementState::Pressed;
-                        input.mouse_down = is_down;
-                        input.mouse_click = is_down;
+                    let was_down = input.mouse_down;
+                    let is_down = state == ElementState::Pressed;
+                    input.mouse_down = is_down;
+                    input.mouse_click = is_down;
+
+                    if is_down {
+                        let now = Instant::now();
+                        let double = last_click.is_some_and(|(time, pos)| {
+                            let d = input.mouse - pos;
+                            now - time < DOUBLE_CLICK_TIME
+                                && d.x.abs() <= DOUBLE_CLICK_DISTANCE
+                                && d.y.abs() <= DOUBLE_CLICK_DISTANCE
+                        });
+
+                        // A third click starts a new double click
+                        input.mouse_double_click = double;
+                        last_click = if double {
+                            None
+                        } else {
+                            Some((now, input.mouse))
+                        };
                     }
                 }
 
+                Event::WindowEvent {
+                    window_id: _,
+                    event:
+                        WindowEvent::MouseInput {
+                            button: MouseButton::Right,
+                            state,
+                            ..
+                        },
+                } => {
+                    input.mouse_right_down = state == ElementState::Pressed;
+                }
+
+                Event::WindowEvent {
+                    window_id: _,
+                    event:
+                        WindowEvent::MouseInput {
+                            button: MouseButton::Other(button),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                } => {
+                    let (back, forward) = MOUSE_BACK_FORWARD;
+                    input.mouse_back |= button == back;
+                    input.mouse_forward |= button == forward;
+                }
+
                 Event::WindowEvent {
                     window_id: _,
                     event:
@@ -156,6 +272,17 @@ impl Loop {
                     input.mouse_scroll += dy;
                 }
 
+                Event::WindowEvent {
+                    window_id: _,
+                    event:
+                        WindowEvent::MouseWheel {
+                            delta: MouseScrollDelta::PixelDelta(delta),
+                            ..
+                        },
+                } => {
+                    input.mouse_scroll += (delta.y / SCROLL_PIXELS_PER_LINE) as f32;
+                }
+
                 Event::WindowEvent {
                     window_id: _,
                     event: WindowEvent::CursorMoved { position: pos, .. },
@@ -177,9 +304,14 @@ impl Loop {
                         input.real_dt_full = current_time - last_frame_time;
                         input.real_dt_update = Instant::now() - current_time;
                         input.keys_click.clear();
+                        input.text.clear();
+                        input.dropped_files.clear();
                         last_frame_time = current_time;
                         input.mouse_scroll = 0.0;
                         input.mouse_click = false;
+                        input.mouse_double_click = false;
+                        input.mouse_back = false;
+                        input.mouse_forward = false;
 
                         while next_frame_time < current_time {
                             next_frame_time += Duration::from_secs_f32(target_dt);
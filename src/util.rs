@@ -5,7 +5,7 @@ use cgmath::Vector3;
 pub type V2<S = f64> = Vector2<S>;
 pub type V3<S = f64> = Vector3<S>;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 pub struct Rect {
     pub min: V2,
     pub max: V2,
@@ -69,3 +69,10 @@ impl Rect {
         self.max.y = self.max.y.max(r.max.y);
     }
 }
+
+/// A complex number with enough digits to tell points `precision` apart
+pub fn format_complex(c: V2, precision: f64) -> String {
+    let digits = (-precision.log10()).ceil().clamp(1.0, 17.0) as usize + 1;
+    let sign = if c.y < 0.0 { '-' } else { '+' };
+    format!("{:.*} {} {:.*}i", digits, c.x, sign, digits, c.y.abs())
+}
@@ -1,15 +1,41 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::domain::Domain;
+use crate::flight::Flight;
 use crate::tilemap::TilePos;
 use crate::util::*;
 
+/// Where the viewport is looking
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    pub offset: V2,
+    pub zoom: f64,
+    pub rotation: f64,
+}
+
 #[derive(Debug)]
 pub struct Viewport {
     pub zoom: f64,
     pub scale: f64,
     pub offset: V2,
+
+    /// Counter clockwise rotation of the view in radians, around the center of the screen
+    pub rotation: f64,
+
     pub size_in_pixels: V2,
     pub size_in_pixels_i: V2<u32>,
     pub move_vel: V2,
     pub drag_anchor: Option<V2<f64>>,
+
+    /// Angle of the mouse around the center of the screen, while rotating with the mouse
+    pub rotate_anchor: Option<f64>,
+
+    /// The animation to a new location, and how far along it we are in seconds
+    flight: Option<(Flight, f64)>,
+
+    /// Limits of the position and zoom
+    pub domain: Domain,
 }
 
 pub struct ViewportInput {
@@ -19,26 +45,122 @@ pub struct ViewportInput {
     pub zoom_center: f64,
     pub scroll_at: (V2<i32>, f64),
     pub drag: Option<V2<i32>>,
+
+    /// Rotation speed in radians per second
+    pub rotate: f64,
+
+    /// Rotate around the center of the screen by dragging this mouse position
+    pub rotate_drag: Option<V2<i32>>,
 }
 
 impl Viewport {
-    pub fn new() -> Self {
+    pub fn new(domain: Domain) -> Self {
         Viewport {
             zoom: 0.,
             scale: 0.,
             size_in_pixels: V2::zero(),
             size_in_pixels_i: V2::zero(),
             offset: V2::zero(),
+            rotation: 0.0,
             move_vel: V2::zero(),
 
             drag_anchor: None,
+            rotate_anchor: None,
+            flight: None,
+            domain,
+        }
+    }
+
+    pub fn location(&self) -> Location {
+        Location {
+            offset: self.offset,
+            zoom: self.zoom,
+            rotation: self.rotation,
+        }
+    }
+
+    /// Jump to a location immediately
+    pub fn set_location(&mut self, location: Location) {
+        self.flight = None;
+        self.move_vel = V2::zero();
+        self.offset = location.offset;
+        self.zoom = location.zoom;
+        self.rotation = location.rotation;
+        self.scale = 0.5_f64.powf(self.zoom);
+    }
+
+    /// Animate to a location, any user input cancels the animation
+    pub fn fly_to(&mut self, location: Location) {
+        self.move_vel = V2::zero();
+        self.flight = Some((Flight::new(self.location(), location), 0.0));
+    }
+
+    /// The location after zooming in by `zoom` levels, while `p` stays at the same place on the
+    /// screen. Negative values zoom out.
+    pub fn zoom_location(&self, p: V2<i32>, zoom: f64) -> Location {
+        let anchor = self.screen_to_world(p);
+        let factor = 0.5_f64.powf(zoom);
+        Location {
+            offset: anchor + (self.offset - anchor) * factor,
+            zoom: self.zoom + zoom,
+            rotation: self.rotation,
+        }
+    }
+
+    /// The location where the rectangle between the screen positions `a` and `b` fills the
+    /// screen, as far as the aspect ratio allows
+    pub fn fit_location(&self, a: V2<i32>, b: V2<i32>) -> Location {
+        let center = (a + b).map(|x| x as f64 * 0.5);
+        let size = (a - b).map(|x| (x.abs() as f64).max(1.0));
+        let fraction = (size.x / self.size_in_pixels.x).max(size.y / self.size_in_pixels.y);
+
+        let center = self.screen_to_world_exact(center);
+        Location {
+            offset: center,
+            zoom: self.zoom - fraction.log2(),
+            rotation: self.rotation,
+        }
+    }
+
+    /// Where we are flying to, or else where we are
+    pub fn target(&self) -> Location {
+        match &self.flight {
+            Some((flight, _)) => flight.target(),
+            None => self.location(),
         }
     }
 
+    pub fn is_flying(&self) -> bool {
+        self.flight.is_some()
+    }
+
     pub fn update(&mut self, input: &ViewportInput) {
         self.size_in_pixels = input.resolution.map(|x| x as f64);
         self.size_in_pixels_i = input.resolution;
 
+        let user_input = input.drag.is_some()
+            || input.rotate_drag.is_some()
+            || input.dir_move != V2::zero()
+            || input.zoom_center != 0.0
+            || input.rotate != 0.0
+            || input.scroll_at.1 != 0.0;
+
+        if user_input {
+            self.flight = None;
+        }
+
+        if let Some((flight, time)) = &mut self.flight {
+            *time += input.dt;
+            let location = flight.at(*time);
+            let done = *time >= flight.duration();
+            self.offset = location.offset;
+            self.zoom = location.zoom;
+            self.rotation = location.rotation;
+            if done {
+                self.flight = None;
+            }
+        }
+
         match input.drag {
             Some(mouse) => {
                 let mouse_world = self.screen_to_world(mouse);
@@ -50,7 +172,8 @@ impl Viewport {
             None => {
                 self.drag_anchor = None;
 
-                self.offset += input.dt * self.scale * input.dir_move;
+                // Move in the direction as seen on the screen
+                self.offset += input.dt * self.scale * self.rotate(input.dir_move);
 
                 // velocity
                 self.offset += input.dt * self.move_vel;
@@ -63,6 +186,24 @@ impl Viewport {
             }
         }
 
+        match input.rotate_drag {
+            Some(mouse) => {
+                let p = mouse.map(|x| x as f64) - self.size_in_pixels / 2.0;
+
+                // The screen y axis points down
+                let angle = (-p.y).atan2(p.x);
+                let anchor = self.rotate_anchor.replace(angle).unwrap_or(angle);
+                self.rotation -= angle - anchor;
+            }
+            None => self.rotate_anchor = None,
+        }
+
+        self.rotation += input.dt * input.rotate;
+
+        // Keep the angle small, so it does not lose precision
+        let pi = std::f64::consts::PI;
+        self.rotation = (self.rotation + pi).rem_euclid(2.0 * pi) - pi;
+
         let mut scroll_world_pos = None;
         let (scroll_pos, scroll_amount) = input.scroll_at;
         if scroll_amount * scroll_amount > 1e-6 {
@@ -72,11 +213,11 @@ impl Viewport {
 
         self.zoom += input.dt * input.zoom_center;
 
-        self.offset.x = self.offset.x.min(3.0).max(-3.0);
-        self.offset.y = self.offset.y.min(3.0).max(-3.0);
+        self.offset = self.domain.clamp(self.offset);
 
-        // zooming in too far will result in overflows, we might go to 128 bit numbers?
-        self.zoom = self.zoom.min(53.0).max(-4.0);
+        // zooming in too far will run out of precision, we might go to 128 bit numbers?
+        let max_zoom = self.domain.max_zoom(self.size_in_pixels.x);
+        self.zoom = self.zoom.clamp(self.domain.min_zoom, max_zoom);
         self.scale = 0.5_f64.powf(self.zoom);
 
         if let Some(scroll_world_pos) = scroll_world_pos {
@@ -85,20 +226,28 @@ impl Viewport {
         }
     }
 
-    pub fn world_to_screen_rect(&self, r: &Rect) -> Rect {
+    /// The corners of a world space rectangle on the screen, the view can be rotated so this is
+    /// not a rectangle on the screen. Ordered as min, (max.x, min.y), (min.x, max.y), max.
+    pub fn world_to_screen_quad(&self, r: &Rect) -> [V2<f32>; 4] {
         let min = r.corner_min();
         let max = r.corner_max();
+        [min, V2::new(max.x, min.y), V2::new(min.x, max.y), max]
+            .map(|p| self.world_to_screen_exact(p).map(|x| x as f32))
+    }
 
-        let min = self.world_to_screen(min).map(|x| x as _);
-        let max = self.world_to_screen(max).map(|x| x as _);
-
-        Rect::min_max(min, max)
+    pub fn world_to_screen(&self, p: V2) -> V2<i32> {
+        let p = self.world_to_screen_exact(p);
+        V2::new(p.x as i32, p.y as i32)
     }
 
-    pub fn world_to_screen(&self, mut p: V2) -> V2<i32> {
+    /// Like `world_to_screen`, but without rounding to whole pixels
+    pub fn world_to_screen_exact(&self, mut p: V2) -> V2 {
         // offset is in world space
         p -= self.offset;
 
+        // the view is rotated, so the world is rotated the other way
+        p = self.unrotate(p);
+
         // y / vp_width
         p /= self.pixel_size();
 
@@ -109,13 +258,16 @@ impl Viewport {
         p.x += self.size_in_pixels.x / 2.0;
         p.y += self.size_in_pixels.y / 2.0;
 
-        V2::new(p.x as i32, p.y as i32)
+        p
     }
 
     /// Convert a screen-space position to a world position as seen by this viewport
     pub fn screen_to_world(&self, p: V2<i32>) -> V2 {
-        let mut p = V2::new(p.x as f64, p.y as f64);
+        self.screen_to_world_exact(p.map(|x| x as f64))
+    }
 
+    /// Like `screen_to_world`, but for positions between pixels
+    pub fn screen_to_world_exact(&self, mut p: V2) -> V2 {
         // make center of screen 0,0
         p.x -= self.size_in_pixels.x / 2.0;
         p.y -= self.size_in_pixels.y / 2.0;
@@ -128,12 +280,26 @@ impl Viewport {
         // zoom
         p *= self.pixel_size();
 
+        p = self.rotate(p);
+
         // offset is in world space
         p += self.offset;
 
         p
     }
 
+    /// Rotate a direction on the screen to a direction in the world
+    pub fn rotate(&self, p: V2) -> V2 {
+        let (s, c) = self.rotation.sin_cos();
+        V2::new(p.x * c - p.y * s, p.x * s + p.y * c)
+    }
+
+    /// Rotate a direction in the world to a direction on the screen
+    pub fn unrotate(&self, p: V2) -> V2 {
+        let (s, c) = self.rotation.sin_cos();
+        V2::new(p.x * c + p.y * s, p.y * c - p.x * s)
+    }
+
     /// scale of the entire viewport
     pub fn scale(&self) -> f64 {
         self.scale
@@ -144,6 +310,15 @@ impl Viewport {
         self.scale() / self.size_in_pixels.x
     }
 
+    /// Build order of a tile, lower is built first.
+    ///
+    /// Coarse levels come first, they are cheap and cover the screen while zooming.
+    /// Within a level the tiles closest to the center of the screen come first.
+    pub fn tile_priority(&self, p: &TilePos) -> f64 {
+        let distance = (p.square().center() - self.offset).magnitude() / self.scale;
+        p.z as f64 + distance.min(1.0)
+    }
+
     /// Returns an iterator with sorted tiles, the ordering is the same according to
     /// the ord implementation for TilePos
     pub fn get_pos_all(&self, pad: i64) -> Vec<TilePos> {
@@ -158,27 +333,48 @@ impl Viewport {
         // z = log(tile_size)/log(1/2)
         // z = -log2(tile_size)
         let px_size = self.pixel_size();
-        let tile_size = px_size * 256.0 as f64;
+        let tile_size = px_size * 256.0;
         let z_max = -tile_size.log2();
         let z_max = z_max.max(0.0).ceil() as i32;
         let z_min = 0; // (z_max - 8).max(0);
 
-        // extra padding in poportion to tile size
+        // The screen in world space, the view can be rotated so the bounding box is larger
         let off = self.offset;
         let viewport_half_size = 0.5 * px_size * self.size_in_pixels;
-
-        fn clamp(v: V2) -> V2 {
-            V2 {
-                x: v.x.min(2.9).max(-2.9),
-                y: v.y.min(2.9).max(-2.9),
-            }
-        }
-
-        let min = clamp(off - viewport_half_size);
-        let max = clamp(off + viewport_half_size);
-
+        let (sin, cos) = self.rotation.sin_cos();
+        let (sin, cos) = (sin.abs(), cos.abs());
+        let bounds_half_size = V2::new(
+            cos * viewport_half_size.x + sin * viewport_half_size.y,
+            sin * viewport_half_size.x + cos * viewport_half_size.y,
+        );
+
+        // Nothing outside the domain is built
+        let min = self.domain.clamp_tiles(off - bounds_half_size);
+        let max = self.domain.clamp_tiles(off + bounds_half_size);
+
+        let mut tiles = Vec::new();
         for z in (z_min as u8)..(z_max as u8 + 1) {
-            TilePos::between(min, max, z, pad, &mut cache);
+            tiles.clear();
+            TilePos::between(min, max, z, pad, &mut tiles);
+
+            // Only the tiles that overlap the rotated screen, with the padding in proportion to
+            // the tile size. Two rectangles overlap if they overlap along the axes of both.
+            let tile_half_size = 0.5 * 0.5_f64.powi(z as i32);
+            let view_half_size = viewport_half_size.map(|x| x + pad as f64 * tile_half_size * 2.0);
+            let world_half_size = V2::new(
+                cos * view_half_size.x + sin * view_half_size.y,
+                sin * view_half_size.x + cos * view_half_size.y,
+            );
+            let tile_extent = tile_half_size * (cos + sin);
+
+            cache.extend(tiles.iter().copied().filter(|p| {
+                let d = p.square().center() - off;
+                let v = self.unrotate(d);
+                d.x.abs() <= world_half_size.x + tile_half_size
+                    && d.y.abs() <= world_half_size.y + tile_half_size
+                    && v.x.abs() <= view_half_size.x + tile_extent
+                    && v.y.abs() <= view_half_size.y + tile_extent
+            }));
         }
 
         cache